@@ -0,0 +1,350 @@
+use crate::byteorder::ReadBytesExt;
+use crate::convert::Convertable;
+use crate::{Error, Result};
+use std::fmt;
+use std::io::Cursor;
+use std::str;
+
+/// Programming of a switch (Net, SubNet, SwIn or SwOut) of an `Address` packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSwitch {
+    /// Leave the switch unchanged, transmitted as 0x7F
+    NoChange,
+    /// Reset the switch to its physical (front panel) value, transmitted as 0x00
+    Reset,
+    /// Program the switch with the given value (7 bits for Net, 4 bits otherwise), transmitted with bit 7 set
+    Program(u8),
+}
+
+impl From<u8> for AddressSwitch {
+    fn from(value: u8) -> AddressSwitch {
+        match value {
+            0x7F => AddressSwitch::NoChange,
+            0x00 => AddressSwitch::Reset,
+            value if value & 0x80 != 0 => AddressSwitch::Program(value & 0x7F),
+            // Values without bit 7 are ignored by the nodes
+            _ => AddressSwitch::NoChange,
+        }
+    }
+}
+
+impl From<AddressSwitch> for u8 {
+    fn from(switch: AddressSwitch) -> u8 {
+        match switch {
+            AddressSwitch::NoChange => 0x7F,
+            AddressSwitch::Reset => 0x00,
+            AddressSwitch::Program(value) => 0x80 | (value & 0x7F),
+        }
+    }
+}
+
+impl Convertable for AddressSwitch {
+    fn from_cursor(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
+        Ok(cursor.read_u8().map_err(Error::CursorEof)?.into())
+    }
+    fn into_buffer(&self, buffer: &mut Vec<u8>) -> Result<()> {
+        buffer.push((*self).into());
+        Ok(())
+    }
+    fn get_test_value() -> Self {
+        AddressSwitch::Program(3)
+    }
+    fn is_equal(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl Convertable for [AddressSwitch; 4] {
+    fn from_cursor(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
+        let mut result = [AddressSwitch::NoChange; 4];
+        for switch in result.iter_mut() {
+            *switch = AddressSwitch::from_cursor(cursor)?;
+        }
+        Ok(result)
+    }
+    fn into_buffer(&self, buffer: &mut Vec<u8>) -> Result<()> {
+        for switch in self.iter() {
+            switch.into_buffer(buffer)?;
+        }
+        Ok(())
+    }
+    fn get_test_value() -> Self {
+        [
+            AddressSwitch::NoChange,
+            AddressSwitch::Reset,
+            AddressSwitch::Program(1),
+            AddressSwitch::Program(0x0F),
+        ]
+    }
+    fn is_equal(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+/// Command of an `Address` packet, port commands hold the port index (0 to 3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressCommand {
+    /// No action
+    None,
+    /// If the node is currently in merge mode, cancel merge mode upon receipt of the next ArtDmx packet
+    CancelMerge,
+    /// The front panel indicators of the node operate normally
+    LedNormal,
+    /// The front panel indicators of the node are disabled and switched off
+    LedMute,
+    /// Rapid flashing of the front panel indicators, used to identify the node
+    LedLocate,
+    /// Reset the Receive Text Field, the Rx flags and the data error flags of the node
+    ResetRxFlags,
+    /// Enable the analysis and debugging mode of the node
+    AnalysisOn,
+    /// Disable the analysis and debugging mode of the node
+    AnalysisOff,
+    /// Failsafe: hold the last state when the data is lost
+    FailHold,
+    /// Failsafe: set the outputs to zero when the data is lost
+    FailZero,
+    /// Failsafe: set the outputs to full when the data is lost
+    FailFull,
+    /// Failsafe: play back the failsafe scene when the data is lost
+    FailScene,
+    /// Record the current output state as the failsafe scene
+    FailRecord,
+    /// Set the DMX port to merge in LTP mode
+    MergeLtp(u8),
+    /// Set the port to output DMX data from the network
+    DirectionTx(u8),
+    /// Set the port to input DMX data to the network
+    DirectionRx(u8),
+    /// Set the DMX port to merge in HTP mode (default)
+    MergeHtp(u8),
+    /// Set the DMX port to output both Art-Net and sACN data
+    ArtNetSel(u8),
+    /// Set the DMX port to output sACN data only
+    AcnSel(u8),
+    /// Clear the DMX output buffer of the port
+    ClearOutput(u8),
+    /// Any other command code
+    Other(u8),
+}
+
+impl From<u8> for AddressCommand {
+    fn from(code: u8) -> AddressCommand {
+        use AddressCommand::*;
+        let port = code & 0x03;
+        match code {
+            0x00 => None,
+            0x01 => CancelMerge,
+            0x02 => LedNormal,
+            0x03 => LedMute,
+            0x04 => LedLocate,
+            0x05 => ResetRxFlags,
+            0x06 => AnalysisOn,
+            0x07 => AnalysisOff,
+            0x08 => FailHold,
+            0x09 => FailZero,
+            0x0A => FailFull,
+            0x0B => FailScene,
+            0x0C => FailRecord,
+            0x10..=0x13 => MergeLtp(port),
+            0x20..=0x23 => DirectionTx(port),
+            0x30..=0x33 => DirectionRx(port),
+            0x50..=0x53 => MergeHtp(port),
+            0x60..=0x63 => ArtNetSel(port),
+            0x70..=0x73 => AcnSel(port),
+            0x90..=0x93 => ClearOutput(port),
+            code => Other(code),
+        }
+    }
+}
+
+impl From<AddressCommand> for u8 {
+    fn from(command: AddressCommand) -> u8 {
+        use AddressCommand::*;
+        match command {
+            None => 0x00,
+            CancelMerge => 0x01,
+            LedNormal => 0x02,
+            LedMute => 0x03,
+            LedLocate => 0x04,
+            ResetRxFlags => 0x05,
+            AnalysisOn => 0x06,
+            AnalysisOff => 0x07,
+            FailHold => 0x08,
+            FailZero => 0x09,
+            FailFull => 0x0A,
+            FailScene => 0x0B,
+            FailRecord => 0x0C,
+            MergeLtp(port) => 0x10 | (port & 0x03),
+            DirectionTx(port) => 0x20 | (port & 0x03),
+            DirectionRx(port) => 0x30 | (port & 0x03),
+            MergeHtp(port) => 0x50 | (port & 0x03),
+            ArtNetSel(port) => 0x60 | (port & 0x03),
+            AcnSel(port) => 0x70 | (port & 0x03),
+            ClearOutput(port) => 0x90 | (port & 0x03),
+            Other(code) => code,
+        }
+    }
+}
+
+impl Convertable for AddressCommand {
+    fn from_cursor(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
+        Ok(cursor.read_u8().map_err(Error::CursorEof)?.into())
+    }
+    fn into_buffer(&self, buffer: &mut Vec<u8>) -> Result<()> {
+        buffer.push((*self).into());
+        Ok(())
+    }
+    fn get_test_value() -> Self {
+        AddressCommand::MergeLtp(2)
+    }
+    fn is_equal(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+data_structure! {
+    #[doc = "ArtAddress is sent by a controller to remotely program a node: its names, its Port-Address switches and various commands."]
+    #[doc = ""]
+    #[doc = "A node replies with an ArtPollReply holding its new settings."]
+    pub struct Address {
+        #[doc = "Determines which version the server has. Will be ARTNET_PROTOCOL_VERSION by default"]
+        pub version: [u8; 2],
+        #[doc = "Bits 14-8 of the 15 bit Port-Address of every port of the node"]
+        pub net_switch: AddressSwitch,
+        #[doc = "The bind index of the programmed node page, 1 is the root device (0 is also accepted for the root device)"]
+        pub bind_index: u8,
+        #[doc = "The null terminated short name of the node, left unchanged when the first byte is null"]
+        pub short_name: [u8; 18],
+        #[doc = "The null terminated long name of the node, left unchanged when the first byte is null"]
+        pub long_name: [u8; 64],
+        #[doc = "Bits 3-0 of the 15 bit Port-Address of each input port"]
+        pub swin: [AddressSwitch; 4],
+        #[doc = "Bits 3-0 of the 15 bit Port-Address of each output port"]
+        pub swout: [AddressSwitch; 4],
+        #[doc = "Bits 7-4 of the 15 bit Port-Address of every port of the node"]
+        pub sub_switch: AddressSwitch,
+        #[doc = "The sACN priority of the node (0 to 200), left unchanged when 255"]
+        pub acn_priority: u8,
+        #[doc = "The command executed by the node"]
+        pub command: AddressCommand,
+    }
+}
+
+/// Null padded copy of a name, truncated so the last byte is always null
+fn copy_name(name: &str, field: &mut [u8]) {
+    let len = name.len().min(field.len() - 1);
+    field.iter_mut().for_each(|byte| *byte = 0);
+    field[..len].copy_from_slice(&name.as_bytes()[..len]);
+}
+
+fn read_name(field: &[u8]) -> &str {
+    let end = field
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(field.len());
+    str::from_utf8(&field[..end]).unwrap_or("")
+}
+
+impl Address {
+    /// Program the short name of the node (truncated to 17 bytes)
+    pub fn set_short_name(&mut self, name: &str) {
+        copy_name(name, &mut self.short_name);
+    }
+
+    /// Program the long name of the node (truncated to 63 bytes)
+    pub fn set_long_name(&mut self, name: &str) {
+        copy_name(name, &mut self.long_name);
+    }
+
+    /// The programmed short name, empty when unchanged
+    pub fn short_name(&self) -> &str {
+        read_name(&self.short_name)
+    }
+
+    /// The programmed long name, empty when unchanged
+    pub fn long_name(&self) -> &str {
+        read_name(&self.long_name)
+    }
+}
+
+impl Default for Address {
+    fn default() -> Address {
+        Address {
+            version: super::ARTNET_PROTOCOL_VERSION,
+            net_switch: AddressSwitch::NoChange,
+            bind_index: 1,
+            short_name: [0; 18],
+            long_name: [0; 64],
+            swin: [AddressSwitch::NoChange; 4],
+            swout: [AddressSwitch::NoChange; 4],
+            sub_switch: AddressSwitch::NoChange,
+            acn_priority: 255,
+            command: AddressCommand::None,
+        }
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Address")
+            .field("version", &self.version)
+            .field("net_switch", &self.net_switch)
+            .field("bind_index", &self.bind_index)
+            .field("short_name", &self.short_name())
+            .field("long_name", &self.long_name())
+            .field("swin", &self.swin)
+            .field("swout", &self.swout)
+            .field("sub_switch", &self.sub_switch)
+            .field("acn_priority", &self.acn_priority)
+            .field("command", &self.command)
+            .finish()
+    }
+}
+
+#[test]
+fn test_address_packet() {
+    use crate::ArtCommand;
+
+    let mut address = Address {
+        net_switch: AddressSwitch::Program(0x12),
+        sub_switch: AddressSwitch::Program(0x3),
+        swout: [
+            AddressSwitch::Program(0),
+            AddressSwitch::Program(1),
+            AddressSwitch::NoChange,
+            AddressSwitch::Reset,
+        ],
+        command: AddressCommand::FailZero,
+        ..Address::default()
+    };
+    address.set_short_name("wall");
+    let bytes = ArtCommand::Address(address).into_buffer().unwrap();
+    assert_eq!(bytes.len(), 107);
+    assert_eq!(&bytes[8..14], &[0x00, 0x60, 0, 14, 0x92, 1]);
+    assert_eq!(&bytes[14..19], b"wall\0");
+    // SwIn, SwOut, SubSwitch, AcnPriority and Command
+    assert_eq!(
+        &bytes[96..107],
+        &[0x7F, 0x7F, 0x7F, 0x7F, 0x80, 0x81, 0x7F, 0x00, 0x83, 0xFF, 0x09]
+    );
+    match ArtCommand::from_buffer(&bytes).unwrap() {
+        ArtCommand::Address(address) => {
+            assert_eq!(address.net_switch, AddressSwitch::Program(0x12));
+            assert_eq!(address.short_name(), "wall");
+            assert_eq!(address.long_name(), "");
+            assert_eq!(address.swout[3], AddressSwitch::Reset);
+            assert_eq!(address.command, AddressCommand::FailZero);
+        }
+        command => panic!("expected an ArtAddress packet, got {:?}", command),
+    }
+}
+
+#[test]
+fn test_address_command_codes() {
+    for code in 0..=255u8 {
+        assert_eq!(u8::from(AddressCommand::from(code)), code);
+    }
+    assert_eq!(AddressCommand::from(0x92), AddressCommand::ClearOutput(2));
+    assert_eq!(AddressSwitch::from(0x05), AddressSwitch::NoChange);
+}
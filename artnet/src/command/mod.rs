@@ -1,13 +1,17 @@
+mod address;
 mod output;
 mod poll;
 mod poll_reply;
+mod sync;
 
 use crate::{Error, Result};
 use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
 
-pub use self::output::Output;
+pub use self::address::{Address, AddressCommand, AddressSwitch};
+pub use self::output::{Output, PortAddress};
 pub use self::poll::Poll;
 pub use self::poll_reply::PollReply;
+pub use self::sync::ArtSync;
 
 /// The ArtCommand, to be used for ArtNet.
 ///
@@ -33,11 +37,11 @@ pub enum ArtCommand {
     /// [Not implemented] This is an ArtNzs data packet. It contains non-zero start code (except RDM) DMX512 information for a single Universe
     Nzs,
 
-    /// [Not implemented] This is an ArtSync data packet. It is used to force synchronous transfer of ArtDmx packets to a node's output
-    Sync,
+    /// This is an ArtSync data packet. It is used to force synchronous transfer of ArtDmx packets to a node's output
+    Sync(ArtSync),
 
-    /// [Not implemented] This is an ArtAddress packet. It contains remote programming information for a Node.
-    Address,
+    /// This is an ArtAddress packet. It contains remote programming information for a Node.
+    Address(Address),
 
     /// [Not implemented] This is an ArtInput packet. It contains enable – disable data for DMX inputs
     Input,
@@ -168,20 +172,21 @@ impl ArtCommand {
             0x2000 => ArtCommand::Poll(
                 Poll::from(data).map_err(|e| Error::OpcodeError("Poll", Box::new(e)))?,
             ),
-            0x2100 => {
-                ArtCommand::PollReply(Box::new(
-                    PollReply::from(data)
-                        .map_err(|e| Error::OpcodeError("PollReply", Box::new(e)))?,
-                ))
-            }
+            0x2100 => ArtCommand::PollReply(Box::new(
+                PollReply::from(data).map_err(|e| Error::OpcodeError("PollReply", Box::new(e)))?,
+            )),
             0x2300 => ArtCommand::DiagData,
             0x2400 => ArtCommand::Command,
             0x5000 => ArtCommand::Output(
                 Output::from(data).map_err(|e| Error::OpcodeError("Output", Box::new(e)))?,
             ),
             0x5100 => ArtCommand::Nzs,
-            0x5200 => ArtCommand::Sync,
-            0x6000 => ArtCommand::Address,
+            0x5200 => ArtCommand::Sync(
+                ArtSync::from(data).map_err(|e| Error::OpcodeError("Sync", Box::new(e)))?,
+            ),
+            0x6000 => ArtCommand::Address(
+                Address::from(data).map_err(|e| Error::OpcodeError("Address", Box::new(e)))?,
+            ),
             0x7000 => ArtCommand::Input,
             0x8000 => ArtCommand::TodRequest,
             0x8100 => ArtCommand::TodData,
@@ -221,8 +226,8 @@ impl ArtCommand {
             ArtCommand::Command => (0x2400, Vec::new()),
             ArtCommand::Output(output) => (0x5000, output.to_bytes()?),
             ArtCommand::Nzs => (0x5100, Vec::new()),
-            ArtCommand::Sync => (0x5200, Vec::new()),
-            ArtCommand::Address => (0x6000, Vec::new()),
+            ArtCommand::Sync(sync) => (0x5200, sync.to_bytes()?),
+            ArtCommand::Address(address) => (0x6000, address.to_bytes()?),
             ArtCommand::Input => (0x7000, Vec::new()),
             ArtCommand::TodRequest => (0x8000, Vec::new()),
             ArtCommand::TodData => (0x8100, Vec::new()),
@@ -1,35 +1,159 @@
 use crate::command::ARTNET_PROTOCOL_VERSION;
+use crate::convert::Convertable;
+use crate::{Error, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Cursor;
 
-data_structure! {
-    #[derive(Debug)]
-    #[doc = "ArtDmx is the data packet used to transfer DMX512 data. The format is identical for Node to Controller, Node to Node and Controller to Node."]
-    #[doc = ""]
-    #[doc = "The Data is output through the DMX O/P port corresponding to the Universe setting. In the absence of received ArtDmx packets, each DMX O/P port re-transmits the same frame continuously. "]
-    #[doc = ""]
-    #[doc = "The first complete DMX frame received at each input port is placed in an ArtDmx packet as above and transmitted as an ArtDmx packet containing the relevant Universe parameter. Each subsequent DMX frame containing new data (different length or different contents) is also transmitted as an ArtDmx packet."]
-    #[doc = ""]
-    #[doc = "Nodes do not transmit ArtDmx for DMX512 inputs that have not received data since power on."]
-    #[doc = ""]
-    #[doc = "However, an input that is active but not changing, will re-transmit the last valid ArtDmx packet at approximately 4-second intervals. (Note. In order to converge the needs of ArtNet and sACN it is recommended that Art-Net devices actually use a re-transmit time of 800mS to 1000mS)."]
-    #[doc = ""]
-    #[doc = "A DMX input that fails will not continue to transmit ArtDmx data."]
-    pub struct Output {
-        #[doc = "Determines which version the server has. Will be ARTNET_PROTOCOL_VERSION by default"]
-        pub version: [u8; 2],
-        #[doc = "The sequence number is used to ensure that ArtDmx packets are used in the correct order. When Art-Net is carried over a medium such as the Internet, it is possible that ArtDmx packets will reach the receiver out of order. This field is incremented in the range 0x01 to 0xff to allow the receiving node to resequence packets."]
-        #[doc = ""]
-        #[doc = "The Sequence field is set to 0x00 to disable this feature"]
-        pub sequence: u8,
-        #[doc = "The physical input port from which DMX512 data was input. This field is for information only. Use Universe for data routing"]
-        pub physical: u8,
-        #[doc = "The 15 bit Port-Address to which this packet is destined"]
-        pub subnet: u16,
-        #[doc = "The length of the DMX512 data array. This value should be an even number in the range 2 – 512."]
-        #[doc = ""]
-        #[doc = "It represents the number of DMX512 channels encoded in packet. NB: Products which convert Art-Net to DMX512 may opt to always send 512 channels."]
-        pub length: u16,
-        #[doc = "A variable length array of DMX512 lighting data"]
-        pub data: Vec<u8>,
+/// The 15 bit Port-Address of an universe, transmitted as the SubUni byte (bits 7-0) followed by the Net byte (bits 14-8)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PortAddress(u16);
+
+impl PortAddress {
+    /// The highest Port-Address
+    pub const MAX: u16 = 0x7FFF;
+
+    /// Create a Port-Address, fails when the address does not fit into 15 bits
+    pub fn new(address: u16) -> Result<PortAddress> {
+        if address > PortAddress::MAX {
+            return Err(Error::InvalidPortAddress(address));
+        }
+        Ok(PortAddress(address))
+    }
+
+    /// Create a Port-Address from its Net (bits 14-8) and SubUni (bits 7-0) bytes, bit 7 of Net is ignored
+    pub fn from_parts(net: u8, sub_uni: u8) -> PortAddress {
+        PortAddress(u16::from(net & 0x7F) << 8 | u16::from(sub_uni))
+    }
+
+    /// The Net byte, bits 14-8 of the Port-Address
+    pub fn net(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    /// The SubUni byte, bits 7-0 of the Port-Address
+    pub fn sub_uni(self) -> u8 {
+        self.0 as u8
+    }
+}
+
+impl From<PortAddress> for u16 {
+    fn from(address: PortAddress) -> u16 {
+        address.0
+    }
+}
+
+impl Convertable for PortAddress {
+    fn from_cursor(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
+        let sub_uni = cursor.read_u8().map_err(Error::CursorEof)?;
+        let net = cursor.read_u8().map_err(Error::CursorEof)?;
+        Ok(PortAddress::from_parts(net, sub_uni))
+    }
+
+    fn into_buffer(&self, buffer: &mut Vec<u8>) -> Result<()> {
+        buffer.push(self.sub_uni());
+        buffer.push(self.net());
+        Ok(())
+    }
+
+    fn get_test_value() -> Self {
+        PortAddress(0x1234)
+    }
+
+    fn is_equal(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+/// ArtDmx is the data packet used to transfer DMX512 data. The format is identical for Node to Controller, Node to Node and Controller to Node.
+///
+/// The Data is output through the DMX O/P port corresponding to the Universe setting. In the absence of received ArtDmx packets, each DMX O/P port re-transmits the same frame continuously.
+///
+/// The first complete DMX frame received at each input port is placed in an ArtDmx packet as above and transmitted as an ArtDmx packet containing the relevant Universe parameter. Each subsequent DMX frame containing new data (different length or different contents) is also transmitted as an ArtDmx packet.
+///
+/// Nodes do not transmit ArtDmx for DMX512 inputs that have not received data since power on.
+///
+/// However, an input that is active but not changing, will re-transmit the last valid ArtDmx packet at approximately 4-second intervals. (Note. In order to converge the needs of ArtNet and sACN it is recommended that Art-Net devices actually use a re-transmit time of 800mS to 1000mS).
+///
+/// A DMX input that fails will not continue to transmit ArtDmx data.
+#[derive(Debug)]
+pub struct Output {
+    /// Determines which version the server has. Will be ARTNET_PROTOCOL_VERSION by default
+    pub version: [u8; 2],
+    /// The sequence number is used to ensure that ArtDmx packets are used in the correct order. When Art-Net is carried over a medium such as the Internet, it is possible that ArtDmx packets will reach the receiver out of order. This field is incremented in the range 0x01 to 0xff to allow the receiving node to resequence packets.
+    ///
+    /// The Sequence field is set to 0x00 to disable this feature
+    pub sequence: u8,
+    /// The physical input port from which DMX512 data was input. This field is for information only. Use Universe for data routing
+    pub physical: u8,
+    /// The 15 bit Port-Address to which this packet is destined
+    pub port_address: PortAddress,
+    /// The length of the DMX512 data array, transmitted high byte first. This value must be an even number in the range 2 – 512 when sending.
+    ///
+    /// It represents the number of DMX512 channels encoded in packet. NB: Products which convert Art-Net to DMX512 may opt to always send 512 channels.
+    pub length: u16,
+    /// A variable length array of DMX512 lighting data, holding `length` channels
+    pub data: Vec<u8>,
+}
+
+impl Output {
+    /// The highest number of channels of a packet
+    pub const MAX_LENGTH: u16 = 512;
+
+    /// Convert this struct to a byte array, fails when the length is not an even number between 2 and 512 or doesn't match the data
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if self.length < 2 || self.length > Output::MAX_LENGTH || self.length % 2 != 0 {
+            return Err(Error::InvalidLength(self.length));
+        }
+        if self.data.len() != self.length as usize {
+            return Err(Error::LengthMismatch(self.length, self.data.len()));
+        }
+        let mut result = Vec::with_capacity(8 + self.data.len());
+        result.extend_from_slice(&self.version);
+        result.push(self.sequence);
+        result.push(self.physical);
+        self.port_address.into_buffer(&mut result)?;
+        result
+            .write_u16::<BigEndian>(self.length)
+            .map_err(Error::CursorEof)?;
+        result.extend_from_slice(&self.data);
+        Ok(result)
+    }
+
+    /// Convert a byte array to an instance of this struct, the data is truncated to the declared length.
+    ///
+    /// Odd lengths are accepted as receivers should not assume the length is even.
+    pub fn from(data: &[u8]) -> Result<Output> {
+        let field = |name, e| Error::DeserializeError(name, Box::new(e));
+        let mut cursor = Cursor::new(data);
+        let version = Convertable::from_cursor(&mut cursor)
+            .map_err(|e| field("Could not deserialize field Output::version", e))?;
+        let sequence = Convertable::from_cursor(&mut cursor)
+            .map_err(|e| field("Could not deserialize field Output::sequence", e))?;
+        let physical = Convertable::from_cursor(&mut cursor)
+            .map_err(|e| field("Could not deserialize field Output::physical", e))?;
+        let port_address = Convertable::from_cursor(&mut cursor)
+            .map_err(|e| field("Could not deserialize field Output::port_address", e))?;
+        let length = cursor.read_u16::<BigEndian>().map_err(|e| {
+            field(
+                "Could not deserialize field Output::length",
+                Error::CursorEof(e),
+            )
+        })?;
+        if length > Output::MAX_LENGTH {
+            return Err(Error::InvalidLength(length));
+        }
+        let remaining = &data[cursor.position() as usize..];
+        if remaining.len() < length as usize {
+            return Err(Error::LengthMismatch(length, remaining.len()));
+        }
+        Ok(Output {
+            version,
+            sequence,
+            physical,
+            port_address,
+            length,
+            data: remaining[..length as usize].to_vec(),
+        })
     }
 }
 
@@ -39,9 +163,111 @@ impl Default for Output {
             version: ARTNET_PROTOCOL_VERSION,
             sequence: 0,
             physical: 0,
-            subnet: 0,
+            port_address: PortAddress::default(),
             length: 0,
             data: Vec::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArtCommand;
+
+    /// Reference packet hand-written from the ArtDmx packet definition of the spec (not a
+    /// network capture): Port-Address 0x0123, sequence 0x2A, 6 channels followed by 2 bytes
+    /// that are not part of the packet
+    #[rustfmt::skip]
+    const REFERENCE: [u8; 26] = [
+        b'A', b'r', b't', b'-', b'N', b'e', b't', 0, // ID
+        0x00, 0x50,                                   // OpCode, low byte first
+        0x00, 0x0E,                                   // ProtVer 14, high byte first
+        0x2A,                                         // Sequence
+        0x01,                                         // Physical
+        0x23,                                         // SubUni
+        0x01,                                         // Net
+        0x00, 0x06,                                   // Length, high byte first
+        0xFF, 0x00, 0x80, 0x10, 0x20, 0x30,           // Data
+        0xDE, 0xAD,                                   // Trailing bytes
+    ];
+
+    fn decode(packet: &[u8]) -> Output {
+        match ArtCommand::from_buffer(packet).unwrap() {
+            ArtCommand::Output(output) => output,
+            command => panic!("expected an ArtDmx packet, got {:?}", command),
+        }
+    }
+
+    #[test]
+    fn decode_reference() {
+        let output = decode(&REFERENCE);
+        assert_eq!(output.version, [0, 14]);
+        assert_eq!(output.sequence, 0x2A);
+        assert_eq!(output.physical, 1);
+        assert_eq!(u16::from(output.port_address), 0x0123);
+        assert_eq!(output.port_address.net(), 0x01);
+        assert_eq!(output.port_address.sub_uni(), 0x23);
+        assert_eq!(output.length, 6);
+        // The trailing bytes are not part of the data
+        assert_eq!(output.data, vec![0xFF, 0x00, 0x80, 0x10, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn reference_round_trip() {
+        let output = decode(&REFERENCE);
+        let bytes = ArtCommand::Output(output).into_buffer().unwrap();
+        assert_eq!(&bytes[..], &REFERENCE[..24]);
+        let output = Output {
+            sequence: 0xFF,
+            port_address: PortAddress::new(0x7FFF).unwrap(),
+            length: 512,
+            data: (0..512).map(|i| i as u8).collect(),
+            ..Output::default()
+        };
+        let bytes = ArtCommand::Output(output).into_buffer().unwrap();
+        assert_eq!(&bytes[14..18], &[0xFF, 0x7F, 0x02, 0x00]);
+        let output = decode(&bytes);
+        assert_eq!(u16::from(output.port_address), 0x7FFF);
+        assert_eq!(output.data.len(), 512);
+        assert_eq!(output.data[511], 0xFF);
+    }
+
+    #[test]
+    fn encode_validates_length() {
+        let output = |length: u16, data: usize| {
+            ArtCommand::Output(Output {
+                length,
+                data: vec![0; data],
+                ..Output::default()
+            })
+            .into_buffer()
+        };
+        assert!(output(2, 2).is_ok());
+        assert!(output(512, 512).is_ok());
+        assert!(output(0, 0).is_err());
+        assert!(output(5, 5).is_err());
+        assert!(output(514, 514).is_err());
+        assert!(output(4, 6).is_err());
+    }
+
+    #[test]
+    fn decode_validates_length() {
+        let mut packet = REFERENCE;
+        packet[17] = 9;
+        assert!(ArtCommand::from_buffer(&packet).is_err());
+        packet[16] = 0x02;
+        packet[17] = 0x02;
+        assert!(ArtCommand::from_buffer(&packet).is_err());
+        // Odd lengths are accepted
+        packet[16] = 0;
+        packet[17] = 5;
+        assert_eq!(decode(&packet).data.len(), 5);
+    }
+
+    #[test]
+    fn port_address() {
+        assert!(PortAddress::new(0x8000).is_err());
+        assert_eq!(u16::from(PortAddress::from_parts(0xFF, 0x01)), 0x7F01);
+    }
+}
@@ -0,0 +1,41 @@
+data_structure! {
+    #[derive(Debug)]
+    #[doc = "ArtSync is used to force synchronous transfer of ArtDmx packets to a node's output. It is sent by the controller after the ArtDmx packets of a frame."]
+    #[doc = ""]
+    #[doc = "A node enters synchronous mode on the first ArtSync it receives: ArtDmx data is buffered and output when the next ArtSync is received. The node returns to non-synchronous mode when no ArtSync is received for 4 seconds."]
+    #[doc = ""]
+    #[doc = "A node receiving broadcast ArtDmx packets ignores ArtSync, and a node only accepts ArtSync from the controller that sent its ArtDmx packets."]
+    pub struct ArtSync {
+        #[doc = "Determines which version the server has. Will be ARTNET_PROTOCOL_VERSION by default"]
+        pub version: [u8; 2],
+        #[doc = "Transmit as zero"]
+        pub aux1: u8,
+        #[doc = "Transmit as zero"]
+        pub aux2: u8,
+    }
+}
+
+impl Default for ArtSync {
+    fn default() -> ArtSync {
+        ArtSync {
+            version: super::ARTNET_PROTOCOL_VERSION,
+            aux1: 0,
+            aux2: 0,
+        }
+    }
+}
+
+#[test]
+fn test_sync_packet() {
+    use crate::ArtCommand;
+
+    let bytes = ArtCommand::Sync(ArtSync::default()).into_buffer().unwrap();
+    assert_eq!(
+        bytes,
+        vec![b'A', b'r', b't', b'-', b'N', b'e', b't', 0, 0x00, 0x52, 0, 14, 0, 0]
+    );
+    match ArtCommand::from_buffer(&bytes).unwrap() {
+        ArtCommand::Sync(sync) => assert_eq!(sync.version, [0, 14]),
+        command => panic!("expected an ArtSync packet, got {:?}", command),
+    }
+}
@@ -24,6 +24,15 @@ pub enum Error {
 
     /// Unknown opcode ID
     UnknownOpcode(u16),
+
+    /// The ArtDmx length is not an even number between 2 and 512
+    InvalidLength(u16),
+
+    /// The ArtDmx length doesn't match the number of data bytes
+    LengthMismatch(u16, usize),
+
+    /// The Port-Address doesn't fit into 15 bits
+    InvalidPortAddress(u16),
 }
 
 impl std::fmt::Display for Error {
@@ -38,6 +47,17 @@ impl std::fmt::Display for Error {
                 write!(fmt, "Could not parse opcode {:?}: {}", opcode, inner)
             }
             Error::UnknownOpcode(opcode) => write!(fmt, "Unknown opcode 0x{:X}", opcode),
+            Error::InvalidLength(length) => write!(fmt, "Invalid ArtDmx length {}", length),
+            Error::LengthMismatch(length, data) => {
+                write!(
+                    fmt,
+                    "ArtDmx length {} doesn't match {} data bytes",
+                    length, data
+                )
+            }
+            Error::InvalidPortAddress(address) => {
+                write!(fmt, "Invalid Port-Address 0x{:X}", address)
+            }
         }
     }
 }
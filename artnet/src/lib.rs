@@ -1,6 +1,9 @@
 //! Contains the [ArtCommand](struct.ArtCommand.html) enum which holds the entire ArtNet protocol v4, as per [https://artisticlicence.com/WebSiteMaster/User%20Guides/art-net.pdf](https://artisticlicence.com/WebSiteMaster/User%20Guides/art-net.pdf)
 //!
-//! ```rust
+//! ```rust,no_run
+//! # use artnet_protocol::*;
+//! # use std::net::{ToSocketAddrs, UdpSocket};
+//! # fn main() -> std::io::Result<()> {
 //! let socket = UdpSocket::bind(("0.0.0.0", 6454))?;
 //! let broadcast_addr = ("255.255.255.255", 6454).to_socket_addrs().unwrap().next().unwrap();
 //! socket.set_broadcast(true).unwrap();
@@ -20,16 +23,17 @@
 //!         ArtCommand::PollReply(reply) => {
 //!             // This is an ArtNet node on the network. We can send commands to it like this:
 //!             let command = ArtCommand::Output(Output {
-//!                 length: 5, // must match your data.len()
-//!                 data: vec![1, 2, 3, 4, 5], // The data we're sending to the node
+//!                 length: 6, // must match your data.len(), an even number between 2 and 512
+//!                 data: vec![1, 2, 3, 4, 5, 6], // The data we're sending to the node
 //!                 ..Output::default()
 //!             });
-//!             let bytes = command.into_bytes().unwrap();
+//!             let bytes = command.into_buffer().unwrap();
 //!             socket.send_to(&bytes, &addr).unwrap();
 //!         },
 //!         _ => {}
 //!     }
 //! }
+//! # }
 //! ```
 #![deny(missing_docs)]
 
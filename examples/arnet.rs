@@ -52,11 +52,10 @@ pub fn main() -> std::io::Result<()> {
                 for x in (0..100).cycle() {
                     // This is an ArtNet node on the network. We can send commands to it like this:
                     for i in 0..3 {
-                        let mut command = ArtCommand::Output(Output {
+                        let command = ArtCommand::Output(Output {
                             length: 512,        // must match your data.len()
                             data: vec![x; 512], // The data we're sending to the node
-                            physical: ,
-                            subnet: i as u16,
+                            port_address: PortAddress::new(i).unwrap(),
                             ..Output::default()
                         });
                         let bytes = command.into_buffer().unwrap();
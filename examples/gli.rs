@@ -17,6 +17,7 @@ use sdl2::render::TextureCreator;
 use sdl2::render::*;
 use sdl2::video::*;
 use sdl2::EventPump;
+use std::convert::TryFrom;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
@@ -33,6 +34,14 @@ pub enum GliError {
     Artnet(artnet_protocol::Error),
     #[fail(display = "I/O error : {:?}", 0)]
     IoError(std::io::Error),
+    #[fail(display = "GLOLA error : {}", _0)]
+    Glola(GError),
+}
+
+impl From<GError> for GliError {
+    fn from(error: GError) -> Self {
+        GliError::Glola(error)
+    }
 }
 
 impl From<io::Error> for GliError {
@@ -61,59 +70,37 @@ macro_rules! regulate_fps {
     };
 }
 
-use artnet_protocol::*;
-use std::net::{SocketAddr, UdpSocket};
-struct ArnetConnector {
-    socket: UdpSocket,
-    broadcast_addr: SocketAddr,
-}
-
-impl ArnetConnector {
-    pub fn new(
-        listen_addr: &str,
-        listen_port: u16,
-        broadcast_addr: SocketAddr,
-    ) -> io::Result<Self> {
-        let socket = UdpSocket::bind((listen_addr, listen_port))?;
-        socket.set_broadcast(true);
-        Ok(Self {
-            socket,
-            broadcast_addr,
-        })
-    }
-
-    /// Performe a udp broadcast and return first received artCommand
-    /// TODO receive multiple driver PollReady command
-    pub fn broadcast(&mut self) -> Result<SocketAddr> {
-        let poll = ArtCommand::Poll(Poll::default()).into_buffer().unwrap();
-        self.socket.send_to(&poll, &self.broadcast_addr).unwrap();
-        let mut buffer = [0u8; 1024];
-        println!("Waiting for drivers ....");
-        let mut cptr = 0;
-        let mut addr = None;
-        while cptr < 2 {
-            let (length, ad) = self.socket.recv_from(&mut buffer).unwrap(); //TODO put a timeout
-            addr = Some(ad);
-            cptr += 1;
-            println!("Client response: len {}  addr {:?}", length, addr);
-        }
-       let res = Ok(addr.unwrap());
-        res
-        // Ok(addr) //TODO here we should match packet type to ensure that is a pollReady but skiped here we just need socketaddress
-    }
+use std::net::SocketAddr;
 
-    pub fn send(&mut self, addr: &SocketAddr, univer: u8, len: usize, dmx: Vec<u8>) -> Result<()> {
-        let mut command = ArtCommand::Output(Output {
-            length: len as u16, // must match your data.len()
-            data: dmx,          // The data we're sending to the node
-            physical: univer,
-            subnet: univer as u16,
-            ..Output::default()
-        });
-        let bytes = command.into_buffer().unwrap();
-        self.socket.send_to(&bytes, addr).unwrap();
-        Ok(())
+/// Discover the Art-Net nodes and return the node of each univer: the node outputting its
+/// Port-Address, or the first node that replied
+fn discover_nodes(
+    transport: &mut UdpTransport,
+    broadcast_addr: SocketAddr,
+    opt: &MappingOptExt,
+) -> Result<Vec<SocketAddr>, GliError> {
+    println!("Waiting for drivers ....");
+    let nodes = discover(transport, &broadcast_addr, DISCOVERY_TIMEOUT)?;
+    for node in nodes.iter() {
+        println!(
+            "Node {} ({}) bind index {} ports {:x?}",
+            node.addr, node.short_name, node.bind_index, node.port_addresses
+        );
     }
+    let first = nodes
+        .first()
+        .ok_or(GError::WrongConfig("no Art-Net node replied"))?;
+    Ok(opt
+        .port_addresses
+        .iter()
+        .map(|port_address| {
+            nodes
+                .iter()
+                .find(|node| node.port_addresses.contains(port_address))
+                .unwrap_or(first)
+                .addr
+        })
+        .collect())
 }
 
 struct DebugRenderer {
@@ -164,7 +151,7 @@ impl DebugRenderer {
         }
     }
 
-    fn dump(&mut self, packet: &[glola::dmx::ArtDmx]) {
+    fn dump(&mut self, packet: &[UniverBuffer]) {
         for (univer_idx, u) in packet.iter().enumerate() {
             let x_univer = univer_idx % self.opt.univer_per_column;
             let y_univer = univer_idx / self.opt.univer_per_column;
@@ -187,9 +174,9 @@ impl DebugRenderer {
                         let mapped_offset = map[idx] * self.opt.pixel_size;
                         let off = idx * self.opt.pixel_size;
                         for i in 0..4 {
-                            buffer[mapped_offset + i] = u.data[off + i];
+                            buffer[mapped_offset + i] = u.slots()[off + i];
                         }
-                        // buffer[mapped_offset + 3] = std::u8::MAX - u.data[off + 3];
+                        // buffer[mapped_offset + 3] = std::u8::MAX - u.slots()[off + 3];
                     }
                 })
                 .expect("Filed to stream texture");
@@ -241,7 +228,7 @@ impl GifLoader {
             if first.is_none() {
                 first = Some(frame.clone());
             }
-            let mut new_frame = vec![0; opt.width * opt.height * opt.pixel_size];
+            let mut new_frame = vec![0; opt.width * opt.height * opt.input_pixel_size];
             
             let mut curr = {
                 dbg!(frame.left, frame.top, frame.width, frame.height);
@@ -270,17 +257,17 @@ impl GifLoader {
             let height_max = std::cmp::min(frame.height as usize, opt.height);
             let mut rd = Cursor::new(&curr);
             let mut wr = Cursor::new(&mut new_frame);
-            let mut line: Vec<u8> = vec![0; opt.width * opt.pixel_size];
+            let mut line: Vec<u8> = vec![0; opt.width * opt.input_pixel_size];
             if (frame.width as usize) < opt.width {
                 for y in 0..height_max {
-                    rd.read_exact(&mut line[0..(frame.width as usize * opt.pixel_size)])?;
+                    rd.read_exact(&mut line[0..(frame.width as usize * opt.input_pixel_size)])?;
                     wr.write_all(&line)?;
                 }
             } else {
                 for y in 0..height_max {
-                    rd.read_exact(&mut line[0..opt.width * opt.pixel_size])?;
+                    rd.read_exact(&mut line[0..opt.width * opt.input_pixel_size])?;
                     rd.seek(std::io::SeekFrom::Current(
-                        (frame.width as i64 - opt.width as i64) * opt.pixel_size as i64,
+                        (frame.width as i64 - opt.width as i64) * opt.input_pixel_size as i64,
                     ))?;
                     wr.write_all(&line)?;
                 }
@@ -294,9 +281,18 @@ impl GifLoader {
     }
 }
 
-fn gif_loop(gif: &str, opt: MappingOpt, hexd: bool, mul: usize, window: bool) {
-    let mut screen = glola::init_arnet_screen(opt.clone());
-    let opt: MappingOptExt = opt.into();
+fn gif_loop(
+    gif: &str,
+    opt: MappingOpt,
+    broadcast_addr: SocketAddr,
+    hexd: bool,
+    mul: usize,
+    window: bool,
+) -> Result<(), GliError> {
+    // Art-Net univers are sent to the discovered nodes when the configuration has no node
+    let discover_node = opt.nodes.is_none() && opt.protocol == Protocol::Artnet;
+    let mut screen = glola::init_arnet_screen(opt.clone())?;
+    let opt = MappingOptExt::try_from(opt)?;
     let gif = GifLoader::load(gif, &opt).expect("Wrong gif file !");
     let mut dbg = if window {
         Some(DebugRenderer::new(mul, opt.clone()))
@@ -306,13 +302,15 @@ fn gif_loop(gif: &str, opt: MappingOpt, hexd: bool, mul: usize, window: bool) {
     let (ins, first) = gif.frames[0].clone();
     let data = first.to_vec();
     let mut cycle = gif.frames.iter().cycle();
-    let broadcast_addr = ("10.0.0.18", 6454)
-        .to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap();
-    let mut connector = ArnetConnector::new("0.0.0.0", 6454, broadcast_addr).unwrap();
-    let reply = connector.broadcast().unwrap();
+    let serializer = glola::init_serializer(&opt)?;
+    let mut router = if discover_node {
+        let mut transport = UdpTransport::bind(("0.0.0.0", ARTNET_PORT))?;
+        let nodes = discover_nodes(&mut transport, broadcast_addr, &opt)?;
+        let transport: Box<dyn Transport + Send> = Box::new(transport);
+        Router::new(serializer, transport, nodes)
+    } else {
+        Router::from_mapping(&opt, serializer, glola::init_transport(&opt)?)
+    };
     for (i, mut frame) in cycle.enumerate() {
         println!("ITER");
         // let mut frame = frame.clone();
@@ -324,28 +322,27 @@ fn gif_loop(gif: &str, opt: MappingOpt, hexd: bool, mul: usize, window: bool) {
         // @Kantum this loop ensure that the gif fps is respected, (ex gif at 10fps must show each frame 6 time)
         let instant = std::time::Instant::now();
         let _ = dbg.as_mut().map(|e| e.poll_event());
-        let (fps, packet) = screen.apply(&frame.1);
-        dbg!(fps);
-        let hot_fix = vec![&packet[0], &packet[1], &packet[4], &packet[5], &packet[2], &packet[3], &packet[6], &packet[7]];
-        for (i, mut u) in hot_fix.into_iter().enumerate() {
-            let mut data = u.data.to_vec();
-            // @Kantum `i` is the univer id, u is the coresponding ArtDmx packet
-            connector.send(&reply, i as u8, 512, data).unwrap();
+        // Frames are repeated or dropped to follow the configured refresh rate
+        let info = screen.present(&frame.1, frame.0, |packet| {
+            router.send(packet)?;
             if hexd {
-                println!("{}", u)
+                packet.iter().for_each(|u| println!("{}", u));
             }
+            let _ = dbg.as_mut().map(|e| e.dump(packet));
+            Ok(())
+        })?;
+        if hexd {
+            println!("{:?}", info);
         }
-        let _ = dbg.as_mut().map(|e| e.dump(packet));
-        
-        std::thread::sleep(frame.0);
-        // @Kantum fps are regulated here, you can change global var FRAME_DELLAY/FPS to modify fps
-        // regulate_fps!(instant.elapsed().as_millis() as f64, FRAME_DELLAY);
     }
-    // }
+    Ok(())
 }
 
 fn dump(opt: MappingOpt) {
-    let opt: MappingOptExt = opt.into();
+    let opt = match MappingOptExt::try_from(opt) {
+        Ok(opt) => opt,
+        Err(e) => return error!("{}", e),
+    };
     let addr = AddrMap::from_mapping(opt.clone());
     println!("{}{:?}", addr, opt);
 }
@@ -406,6 +403,12 @@ fn main() {
                     Arg::with_name("hexdump")
                         .short("h")
                         .help("Hexdump every outgoing packet on the standard output.")
+                )
+                .arg(
+                    Arg::with_name("broadcast")
+                        .short("b")
+                        .takes_value(true)
+                        .help("Address used to discover the node when the configuration doesn't provide `nodes` (default 255.255.255.255:6454).")
                 ),
         )
         .subcommand(
@@ -432,7 +435,14 @@ fn main() {
         }
         .parse::<usize>()
         .expect("Multiplier must be a positive integer");
-        gif_loop(gif, config, hexdump, multiplier, window);
+        let broadcast_addr: SocketAddr = cmd
+            .value_of("broadcast")
+            .unwrap_or("255.255.255.255:6454")
+            .parse()
+            .expect("Broadcast must be a socket address (ex: 10.0.0.255:6454)");
+        if let Err(e) = gif_loop(gif, config, broadcast_addr, hexdump, multiplier, window) {
+            error!("{}", e);
+        }
     } else if let Some(cmd) = matches.subcommand_matches("dump") {
         dump(config!(cmd.value_of("config").unwrap()));
     } else {
@@ -0,0 +1,151 @@
+//!
+//! Color correction (gamma curve and white balance) applied to input frames before encoding
+//!
+
+use crate::prelude::*;
+
+/// Number of steps of the lookup tables, values between two steps are interpolated
+const LUT_STEPS: u32 = 4096;
+
+impl CorrectionOpt {
+    pub fn validate(&self) -> Result<(), GError> {
+        if !self.gamma.is_finite() || self.gamma <= 0.0 {
+            return Err(GError::InvalidGamma { gamma: self.gamma });
+        }
+        if let Some(&value) = self
+            .white_balance
+            .iter()
+            .find(|value| !(0.0..=1.0).contains(*value))
+        {
+            return Err(GError::InvalidWhiteBalance { value });
+        }
+        Ok(())
+    }
+}
+
+/// Precomputed lookup tables of a `CorrectionOpt` (one per color channel),
+/// no table is built for the default (identity) correction so it stays lossless
+#[derive(Debug, Clone)]
+pub struct ColorCorrection {
+    lut: Option<[Vec<u16>; 3]>,
+}
+
+impl ColorCorrection {
+    pub fn new(opt: &CorrectionOpt) -> Self {
+        if *opt == CorrectionOpt::default() {
+            return Self { lut: None };
+        }
+        let table = |white_balance: f32| {
+            (0..=LUT_STEPS)
+                .map(|step| {
+                    let input = f64::from(step) / f64::from(LUT_STEPS);
+                    let value = input.powf(f64::from(opt.gamma)) * f64::from(white_balance);
+                    (value * f64::from(u16::MAX)).round() as u16
+                })
+                .collect()
+        };
+        Self {
+            lut: Some([
+                table(opt.white_balance[0]),
+                table(opt.white_balance[1]),
+                table(opt.white_balance[2]),
+            ]),
+        }
+    }
+
+    /// Correct a single 16 bits value of the given channel (0: red, 1: green, 2: blue)
+    pub fn value(&self, channel: usize, value: u16) -> u16 {
+        let lut = match &self.lut {
+            Some(lut) => &lut[channel],
+            None => return value,
+        };
+        let max = u32::from(u16::MAX);
+        let scaled = u32::from(value) * LUT_STEPS;
+        let step = (scaled / max) as usize;
+        let frac = scaled % max;
+        if frac == 0 {
+            return lut[step];
+        }
+        let (low, high) = (u32::from(lut[step]), u32::from(lut[step + 1]));
+        ((low * (max - frac) + high * frac + max / 2) / max) as u16
+    }
+
+    /// Correct the red, green and blue channels of a 16 bits RGBA buffer
+    pub fn apply(&self, input: &[u16], output: &mut [u16]) {
+        if self.lut.is_none() {
+            output.copy_from_slice(input);
+            return;
+        }
+        for (src, dst) in input.chunks(4).zip(output.chunks_mut(4)) {
+            for channel in 0..3 {
+                dst[channel] = self.value(channel, src[channel]);
+            }
+            dst[3] = src[3];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn correction(gamma: f32, white_balance: [f32; 3]) -> ColorCorrection {
+        ColorCorrection::new(&CorrectionOpt {
+            gamma,
+            white_balance,
+        })
+    }
+
+    #[test]
+    fn identity() {
+        let correction = correction(1.0, [1.0; 3]);
+        for value in (0..=u16::MAX).step_by(97) {
+            assert_eq!(correction.value(0, value), value);
+        }
+        assert_eq!(correction.value(0, u16::MAX), u16::MAX);
+    }
+
+    #[test]
+    fn linear_table_is_accurate() {
+        // Not the default correction so the lookup table is used
+        let correction = correction(1.0, [1.0, 1.0, 0.5]);
+        for value in (0..=u16::MAX).step_by(97) {
+            let corrected = i32::from(correction.value(0, value));
+            assert!((corrected - i32::from(value)).abs() <= 1, "{}", value);
+        }
+    }
+
+    #[test]
+    fn gamma_curve() {
+        let correction = correction(2.2, [1.0; 3]);
+        // (128 / 255) ^ 2.2 * 255 = 55.98
+        assert_eq!(correction.value(1, upscale(128)) >> 8, 56);
+        // (64 / 255) ^ 2.2 * 255 = 12.25
+        assert_eq!(correction.value(1, upscale(64)) >> 8, 12);
+        assert_eq!(correction.value(1, 0), 0);
+        assert_eq!(correction.value(1, u16::MAX), u16::MAX);
+    }
+
+    #[test]
+    fn white_balance() {
+        let correction = correction(1.0, [1.0, 0.5, 0.25]);
+        let mut output = [0; 4];
+        correction.apply(&[0xFFFF, 0xFFFF, 0xFFFF, 0x1234], &mut output);
+        assert_eq!(output, [0xFFFF, 0x8000, 0x4000, 0x1234]);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(CorrectionOpt::default().validate().is_ok());
+        let opt = CorrectionOpt {
+            gamma: f32::NAN,
+            white_balance: [1.0; 3],
+        };
+        assert!(opt.validate().is_err());
+        let opt = CorrectionOpt {
+            gamma: 2.2,
+            white_balance: [1.0, -0.1, 1.0],
+        };
+        assert!(opt.validate().is_err());
+    }
+}
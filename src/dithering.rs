@@ -0,0 +1,75 @@
+//!
+//! Temporal dithering: quantize 16 bits frames to 8 bits levels while carrying the
+//! quantization error to the next frames so that the average level stays accurate
+//!
+
+/// Distance between two 8 bits levels in the 16 bits range (0xFF * 257 = 0xFFFF)
+const LEVEL: u32 = 257;
+
+#[derive(Debug, Clone)]
+pub struct TemporalDithering {
+    /// Quantization error carried by each value of the frame (always lesser than `LEVEL`)
+    error: Vec<u16>,
+}
+
+impl TemporalDithering {
+    pub fn new(len: usize) -> Self {
+        Self {
+            error: vec![0; len],
+        }
+    }
+
+    /// Quantize a 16 bits buffer in place, every value becomes an 8 bits level
+    /// (`level * 257`) so encoders keep it as is when truncating to 8 bits
+    pub fn apply(&mut self, buffer: &mut [u16]) {
+        for (value, error) in buffer.iter_mut().zip(self.error.iter_mut()) {
+            let total = u32::from(*value) + u32::from(*error);
+            let level = std::cmp::min(total / LEVEL, 0xFF);
+            *error = std::cmp::min(total - level * LEVEL, LEVEL - 1) as u16;
+            *value = (level * LEVEL) as u16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::upscale;
+
+    #[test]
+    fn exact_levels_are_kept() {
+        let mut dithering = TemporalDithering::new(3);
+        for _ in 0..4 {
+            let mut buffer = [upscale(0), upscale(100), upscale(255)];
+            dithering.apply(&mut buffer);
+            assert_eq!(buffer, [upscale(0), upscale(100), upscale(255)]);
+        }
+    }
+
+    #[test]
+    fn average_level_over_frames() {
+        let mut dithering = TemporalDithering::new(2);
+        // Slightly above 100.25 and 0.5 in 8 bits levels
+        let input = [upscale(100) + 65, 129];
+        let mut sum = [0; 2];
+        for _ in 0..4 {
+            let mut buffer = input;
+            dithering.apply(&mut buffer);
+            for (sum, value) in sum.iter_mut().zip(buffer.iter()) {
+                assert_eq!(u32::from(*value) % LEVEL, 0);
+                *sum += u32::from(*value) / LEVEL;
+            }
+        }
+        assert_eq!(sum, [401, 2]);
+    }
+
+    #[test]
+    fn full_level_does_not_overflow() {
+        let mut dithering = TemporalDithering::new(1);
+        for _ in 0..4 {
+            let mut buffer = [0xFFFF];
+            dithering.apply(&mut buffer);
+            assert_eq!(buffer, [0xFFFF]);
+        }
+    }
+}
@@ -1,48 +1,266 @@
 //! Based on https://art-net.org.uk/structure/streaming-packets/artdmx-packet-definition/
+//!
+//! `ArtDmx` reads and writes the fields of a packet stored in a byte buffer, the packet bytes
+//! are the same as the `artnet_protocol::ArtCommand::Output` ones. Each `UniverBuffer` owns an
+//! `ArtDmx` packet, the encoder writes the slots in its data and the serializer sends it as is
 
-/// Dont modify fields order they match with DMX512 definition and
-#[derive(Clone)]
-#[repr(packed)]
-pub struct ArtDmx {
-    /// The ID field is 8 bytes in length and contains the null terminated string of ASCII characters: Art-Net
-    pub id: [u8; 8],
-    /// The purpose of the field is to identify the packet as an Art-Net protocol packet. All Art-Net packets start with this field. Receivers must check this field is correct and discard the packet if it is not.
-    /// The OpCode field is a 16-bit word transmitted low byte first. The OpCode identifies the packet type. In the ArtDmx packet this field is set to ‘OpDmx’.
-    pub op_code: u16,
-    /// The ProtVer field is a 16-bit word transmitted high byte first. It identifies the version of the Art-Net protocol. The protocol version is not related to Art-Net 1, II, 3 or 4
-    ///. Only one protocol version has ever existed in production equipment which is 1410. Receivers should use the following good packet test: ProtVer >= 14
-    /// The ProtVer may be increased in a future releases to identify significant functionality changes.
-    pub proto_ver: [u8; 2],
-    /// The Sequence field is an 8-bit number that is designed to show the order in which packets were originated. When Art-Net is transferred over media such as the Internet, it is possible for packets to arrive at their destination out of sequence. This field allows the receiver to trap such errors.
-    /// The generating device increments this number for every packet sent to a specific Port-Address. The number increments from 110 to 25510 and then rolls over to 110 and repeats. This is because the value 010 is reserved to show that Sequence is not implemented.
-    pub sequence: u8,
-    /// The Physical field is an 8-bit number that defines the physical port that generated the packet. This number is limited to the range 010 to 310.
-    ///It is intended to be purely informative and is not used to define the destination of the packet.
-    pub physical: u8,
-    /// Net and SubUni are combined to form the 15-bit Port-Address to which this packet is directed. The low 7-bits of the Net field define bits 14-8 of the 15-bit Port-Address.
-    /// The 8-bits of the SubUni field define bits 7-0 of the 15-bit Port-Address.
-    /// The diagram below shows the bit allocation within the Net field.
-    ///
-    /// *bit*  *net*
-    /// 7  Not used, set to 0
-    /// 6  Port-address bit-14
-    /// 5  Port-address bit-13
-    /// 4  Port-address bit-12
-    /// 3  Port-address bit-11
-    /// 2  Port-address bit-10
-    /// 1  Port-address bit-9
-    /// 0  Port-address bit-8
-    ///
-    /// The bit organisation of the Port-Address is shown in the diagram below.
-    /// port-addr net switchUni
-    /// *bits* *12-8* *7-0*
-    pub sub_uni: u8,
-    pub net: u8,
-    /// The Length field is a 16-bit number transmitted most significant byte first. It defines the number of bytes encoded in the Data[] field. The Length field can be set to any even value in the range 210 – 51210.
-    /// The requirement thaA value greater that 51210 is an error and receivers should discard the value.
-    /// it be an even number is something of a historical anomaly. It was intended to ensure that when receivers are reading the payload using 16-bit reads, a buffer overrun would not occur on the last odd byte. In reality, implementers have widely ignored this requirement and so designers of receivers should not assume that this field will be an even number.
-    pub lenght: u16,
-    /// The Data field contains the data slot (channel levels). The size of this array is defined by the Length field.
-    /// The first 8-bit entry in the Data field is always data-slot 1 or channel 1.
-    pub data: [u8; 512],
+use crate::GError;
+
+/// The ID field contains the null terminated string of ASCII characters: Art-Net
+pub const ART_NET_ID: [u8; 8] = *b"Art-Net\0";
+/// OpCode of the ArtDmx packet (transmitted low byte first)
+pub const OP_DMX: u16 = 0x5000;
+/// Only one protocol version has ever existed in production equipment which is 14
+pub const PROTOCOL_VERSION: u16 = 14;
+/// Size of the header preceding the data slots
+pub const ART_DMX_HEADER_SIZE: usize = 18;
+/// Maximum number of data slots
+pub const ART_DMX_MAX_LENGTH: usize = 512;
+
+const OP_CODE: usize = 8;
+const PROT_VER: usize = 10;
+const SEQUENCE: usize = 12;
+const PHYSICAL: usize = 13;
+const SUB_UNI: usize = 14;
+const NET: usize = 15;
+const LENGTH: usize = 16;
+
+/// Length field of `slots` data slots: an even number between 2 and 512
+pub fn art_dmx_length(slots: usize) -> usize {
+    let length = slots.clamp(2, ART_DMX_MAX_LENGTH);
+    length + length % 2
+}
+
+/// ArtDmx packet over a byte buffer
+#[derive(Debug, Clone)]
+pub struct ArtDmx<B> {
+    buffer: B,
+}
+
+impl<B: AsRef<[u8]>> ArtDmx<B> {
+    /// Check the header of a received packet, the buffer must hold the `Length` data slots
+    pub fn new_checked(buffer: B) -> Result<Self, GError> {
+        let packet = Self { buffer };
+        let bytes = packet.buffer.as_ref();
+        if bytes.len() < ART_DMX_HEADER_SIZE {
+            return Err(GError::InvalidPacket("ArtDmx packet too short"));
+        }
+        if bytes[..OP_CODE] != ART_NET_ID || packet.op_code() != OP_DMX {
+            return Err(GError::InvalidPacket("not an ArtDmx packet"));
+        }
+        if packet.version() < PROTOCOL_VERSION {
+            return Err(GError::InvalidPacket("unsupported Art-Net version"));
+        }
+        if packet.length() > ART_DMX_MAX_LENGTH {
+            return Err(GError::InvalidPacket("ArtDmx length exceed 512"));
+        }
+        if bytes.len() < ART_DMX_HEADER_SIZE + packet.length() {
+            return Err(GError::InvalidPacket("ArtDmx data shorter than its length"));
+        }
+        Ok(packet)
+    }
+
+    fn field(&self, offset: usize) -> [u8; 2] {
+        let bytes = self.buffer.as_ref();
+        [bytes[offset], bytes[offset + 1]]
+    }
+
+    /// The OpCode field is transmitted low byte first
+    pub fn op_code(&self) -> u16 {
+        u16::from_le_bytes(self.field(OP_CODE))
+    }
+
+    /// The ProtVer field is transmitted high byte first
+    pub fn version(&self) -> u16 {
+        u16::from_be_bytes(self.field(PROT_VER))
+    }
+
+    /// Incremented from 1 to 255 for every packet sent to a Port-Address, 0 disables the sequence
+    pub fn sequence(&self) -> u8 {
+        self.buffer.as_ref()[SEQUENCE]
+    }
+
+    /// Physical input port, purely informative
+    pub fn physical(&self) -> u8 {
+        self.buffer.as_ref()[PHYSICAL]
+    }
+
+    /// 15-bit Port-Address made from the Net (bits 14-8) and SubUni (bits 7-0) fields
+    pub fn port_address(&self) -> u16 {
+        ((self.buffer.as_ref()[NET] as u16 & 0x7F) << 8) | self.buffer.as_ref()[SUB_UNI] as u16
+    }
+
+    /// Number of data slots, transmitted high byte first
+    pub fn length(&self) -> usize {
+        u16::from_be_bytes(self.field(LENGTH)) as usize
+    }
+
+    /// Data slots, the first entry is slot 1
+    pub fn data(&self) -> &[u8] {
+        &self.buffer.as_ref()[ART_DMX_HEADER_SIZE..ART_DMX_HEADER_SIZE + self.length()]
+    }
+
+    /// Bytes of the packet (header and data)
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer.as_ref()[..ART_DMX_HEADER_SIZE + self.length()]
+    }
+
+    /// Bytes following the header, the data slots and the rest of the buffer
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[ART_DMX_HEADER_SIZE..]
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> ArtDmx<B> {
+    /// Write the header of a packet of `length` slots, the buffer must hold the data slots
+    pub fn new(mut buffer: B, length: usize) -> Self {
+        {
+            let bytes = buffer.as_mut();
+            bytes[..OP_CODE].copy_from_slice(&ART_NET_ID);
+            bytes[OP_CODE..PROT_VER].copy_from_slice(&OP_DMX.to_le_bytes());
+            bytes[PROT_VER..SEQUENCE].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+            bytes[SEQUENCE] = 0;
+            bytes[PHYSICAL] = 0;
+            bytes[SUB_UNI] = 0;
+            bytes[NET] = 0;
+            bytes[LENGTH..ART_DMX_HEADER_SIZE].copy_from_slice(&(length as u16).to_be_bytes());
+        }
+        Self { buffer }
+    }
+
+    pub fn set_sequence(&mut self, sequence: u8) {
+        self.buffer.as_mut()[SEQUENCE] = sequence;
+    }
+
+    pub fn set_physical(&mut self, physical: u8) {
+        self.buffer.as_mut()[PHYSICAL] = physical;
+    }
+
+    pub fn set_port_address(&mut self, port_address: u16) {
+        let [net, sub_uni] = port_address.to_be_bytes();
+        self.buffer.as_mut()[SUB_UNI] = sub_uni;
+        self.buffer.as_mut()[NET] = net & 0x7F;
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        let end = ART_DMX_HEADER_SIZE + self.length();
+        &mut self.buffer.as_mut()[ART_DMX_HEADER_SIZE..end]
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer.as_mut()[ART_DMX_HEADER_SIZE..]
+    }
+}
+
+impl<B: AsRef<[u8]>> std::fmt::Display for ArtDmx<B> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            fmt,
+            "OP: {:#06x}, VER: {}, SEQ: {}, PORT: {:#06x}, LEN: {}",
+            self.op_code(),
+            self.version(),
+            self.sequence(),
+            self.port_address(),
+            self.length()
+        )?;
+        for line in hexdump::hexdump_iter(self.data()) {
+            writeln!(fmt, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{serialize_packets, ArtnetSerializer, UniverBuffer};
+    use artnet_protocol::ArtCommand;
+
+    /// ArtDmx packet of the spec layout: Port-Address 0x1234, sequence 7, 4 slots
+    #[rustfmt::skip]
+    const GOLDEN: [u8; 22] = [
+        b'A', b'r', b't', b'-', b'N', b'e', b't', 0,
+        0x00, 0x50, // OpDmx, low byte first
+        0, 14,      // ProtVer, high byte first
+        7,          // Sequence
+        0,          // Physical
+        0x34,       // SubUni
+        0x12,       // Net
+        0, 4,       // Length, high byte first
+        1, 2, 3, 4,
+    ];
+
+    #[test]
+    fn write_golden_packet() {
+        let mut buffer = [0xAA; 22];
+        let mut packet = ArtDmx::new(&mut buffer[..], 4);
+        packet.set_sequence(7);
+        packet.set_port_address(0x1234);
+        packet.data_mut().copy_from_slice(&[1, 2, 3, 4]);
+        assert_eq!(packet.as_bytes(), &GOLDEN[..]);
+    }
+
+    #[test]
+    fn read_golden_packet() {
+        let packet = ArtDmx::new_checked(&GOLDEN[..]).unwrap();
+        assert_eq!(packet.op_code(), OP_DMX);
+        assert_eq!(packet.version(), 14);
+        assert_eq!(packet.sequence(), 7);
+        assert_eq!(packet.port_address(), 0x1234);
+        assert_eq!(packet.data(), &[1, 2, 3, 4]);
+        match ArtCommand::from_buffer(&GOLDEN).unwrap() {
+            ArtCommand::Output(output) => assert_eq!(output.data, vec![1, 2, 3, 4]),
+            _ => panic!("expected an ArtDmx packet"),
+        }
+    }
+
+    #[test]
+    fn data_is_truncated_to_length() {
+        let mut buffer = GOLDEN.to_vec();
+        buffer[17] = 2;
+        buffer.extend_from_slice(&[5, 6]);
+        assert_eq!(ArtDmx::new_checked(&buffer[..]).unwrap().data(), &[1, 2]);
+    }
+
+    #[test]
+    fn check_rejects_invalid_packets() {
+        assert!(ArtDmx::new_checked(&GOLDEN[..17]).is_err());
+        assert!(ArtDmx::new_checked(&GOLDEN[..21]).is_err());
+        let mut buffer = GOLDEN;
+        buffer[8] = 0x01;
+        assert!(ArtDmx::new_checked(&buffer[..]).is_err());
+        let mut buffer = GOLDEN;
+        buffer[11] = 13;
+        assert!(ArtDmx::new_checked(&buffer[..]).is_err());
+    }
+
+    /// Round trip of a captured ArtDmx packet: the UDP payload of a packet sent by a console or
+    /// a node, exported from Wireshark with "Export Packet Bytes", ex:
+    /// `ARTNET_CAPTURE=art_dmx.bin cargo test captured -- --ignored`
+    #[test]
+    #[ignore]
+    fn captured_packet_round_trip() {
+        let path = std::env::var("ARTNET_CAPTURE").expect("ARTNET_CAPTURE is not set");
+        let capture = std::fs::read(path).unwrap();
+        let packet = ArtDmx::new_checked(&capture[..]).unwrap();
+        match ArtCommand::from_buffer(&capture).unwrap() {
+            ArtCommand::Output(output) => assert_eq!(output.data, packet.data()),
+            _ => panic!("expected an ArtDmx packet"),
+        }
+        let mut univer = UniverBuffer::with_slots(packet.port_address(), packet.data());
+        univer.set_sequence(packet.sequence());
+        let mut serialized = serialize_packets(&mut ArtnetSerializer::default(), &univer).remove(0);
+        // glola doesn't tell the physical port and only speaks the version 14
+        serialized[PHYSICAL] = packet.physical();
+        serialized[PROT_VER..SEQUENCE].copy_from_slice(&packet.version().to_be_bytes());
+        assert_eq!(&serialized[..], packet.as_bytes());
+    }
+
+    #[test]
+    fn length_is_even() {
+        assert_eq!(art_dmx_length(0), 2);
+        assert_eq!(art_dmx_length(3), 4);
+        assert_eq!(art_dmx_length(510), 510);
+        assert_eq!(art_dmx_length(512), 512);
+    }
 }
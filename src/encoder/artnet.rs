@@ -2,70 +2,547 @@
 //! Based on https://artisticlicence.com/WebSiteMaster/User%20Guides/art-net.pdf
 //!
 
-use super::Encoder;
+use super::{Encoder, SendTo, Serializer, UniverBuffer};
 use crate::dmx::ArtDmx;
 use crate::prelude::*;
+use artnet_protocol::{ArtCommand, ArtSync};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 
+/// Time (milliseconds) after which a node without ArtSync returns to non-synchronous mode
+pub const ARTSYNC_TIMEOUT: u64 = 4000;
+
+/// Map pixels to the DMX slots of their univer
+#[derive(Clone)]
 pub struct ArtnetEncoder {
     opt: MappingOptExt,
-    univers: Vec<ArtDmx>,
-}
-
-impl std::fmt::Display for ArtDmx {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        writeln!(
-            fmt,
-            "ID: {:?}, OP: {:?}, VER: {:?}, LEN: {:?}",
-            self.id, self.op_code, self.proto_ver, self.lenght
-        )?;
-        for line in hexdump::hexdump_iter(&self.data[0..self.lenght as usize]) {
-            write!(fmt, "{}\n", line)?;
-        }
-        Ok(())
-    }
-}
-
-impl ArtDmx {
-    fn new(opt: &MappingOptExt) -> Self {
-        Self {
-            id: [b'A', b'r', b't', b'-', b'N', b'e', b't', b'\0'],
-            op_code: 0x5000, // OpOutput
-            proto_ver: [5, 57],
-            sequence: 0,
-            physical: 0,
-            sub_uni: 0, //TODO
-            net: 0,     //TODO
-            lenght: (opt.univer_width * opt.univer_height * opt.pixel_size) as u16,
-            data: [0; 512],
-        }
-    }
+    univers: Vec<UniverBuffer>,
+    transform: ChannelTransform,
+    /// Univers are numbered, except the ArtDmx packets when disabled by the configuration
+    numbered: bool,
+    /// Sequence number of the last frame of each Port-Address
+    sequences: HashMap<u16, u8>,
 }
 
 impl ArtnetEncoder {
     pub fn new(opt: MappingOptExt) -> Self {
+        let univer_size = opt.univer_width * opt.univer_height * opt.pixel_size;
         Self {
-            univers: vec![ArtDmx::new(&opt); opt.univer_per_column * opt.univer_per_row],
+            univers: opt
+                .port_addresses
+                .iter()
+                .take(opt.univer_per_column * opt.univer_per_row)
+                .map(|port_address| UniverBuffer::new(*port_address, univer_size))
+                .collect(),
+            transform: ChannelTransform::new(&opt),
+            numbered: opt.protocol != Protocol::Artnet || opt.artnet.sequence,
+            sequences: HashMap::new(),
             opt,
         }
     }
 }
 
 impl Encoder for ArtnetEncoder {
-    fn encode<'a>(&'a mut self, matrix: &AddrMap, buffer: &[u8]) -> &'a [ArtDmx] {
+    fn encode<'a>(&'a mut self, matrix: &AddrMap, buffer: &[u16]) -> &'a [UniverBuffer] {
         for y in 0..self.opt.height {
-            let buffer_row_offset = y * self.opt.width * matrix.opt.pixel_size;
+            let buffer_row_offset = y * self.opt.width * matrix.opt.input_pixel_size;
             for x in 0..self.opt.width {
                 let PixelAddr { address, univer } = matrix.addr[x][y];
-                let buffer_offset = (x * matrix.opt.pixel_size) + buffer_row_offset;
-                self.univers[univer].data[address..address + self.opt.pixel_size]
-                    .copy_from_slice(&buffer[buffer_offset..buffer_offset + self.opt.pixel_size]);
-                // @Kantum modulate led brightness here (for test prupose)
-                self.univers[univer].data[address + 0];
-                self.univers[univer].data[address + 1];
-                self.univers[univer].data[address + 2];
-                self.univers[univer].data[address + 3] = 0;
+                let buffer_offset = (x * matrix.opt.input_pixel_size) + buffer_row_offset;
+                // Written in place in the ArtDmx packet of the univer
+                self.transform.apply(
+                    &buffer[buffer_offset..buffer_offset + self.opt.input_pixel_size],
+                    &mut self.univers[univer].slots_mut()[address..address + self.opt.pixel_size],
+                );
+            }
+        }
+        if self.numbered {
+            for univer in self.univers.iter_mut() {
+                let sequence = self.sequences.entry(univer.port_address()).or_insert(0);
+                // 0 means not numbered, wrap from 255 to 1
+                *sequence = *sequence % 255 + 1;
+                univer.set_sequence(*sequence);
             }
         }
         &self.univers
     }
 }
+
+/// Serialize univers into ArtDmx packets
+#[derive(Debug, Clone, Default)]
+pub struct ArtnetSerializer {
+    sync: bool,
+}
+
+impl ArtnetSerializer {
+    pub fn new(opt: &ArtnetOpt) -> Self {
+        Self { sync: opt.sync }
+    }
+}
+
+/// Limited broadcast address (a node ignores ArtSync when it receives broadcast ArtDmx),
+/// the netmask is unknown so a directed broadcast address can't be told from a node address
+fn is_broadcast(node: &SocketAddr) -> bool {
+    match node.ip() {
+        IpAddr::V4(ip) => ip.is_broadcast(),
+        IpAddr::V6(_) => false,
+    }
+}
+
+impl Serializer for ArtnetSerializer {
+    fn serialize(
+        &mut self,
+        univer: &UniverBuffer,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        // The packet was filled by the encoder, odd univers are padded with a zero slot
+        send(univer.art_dmx()?.as_bytes())
+    }
+
+    /// One ArtSync per node, frames with broadcast univers stay non-synchronous
+    fn end_frame(&mut self, nodes: &[SocketAddr], send: &mut SendTo) -> Result<(), GError> {
+        if !self.sync || nodes.iter().any(is_broadcast) {
+            return Ok(());
+        }
+        let packet = ArtCommand::Sync(ArtSync::default()).into_buffer()?;
+        for node in nodes {
+            send(&packet, node)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sequence of a received ArtDmx packet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SequenceStatus {
+    /// The sender doesn't number its packets
+    Disabled,
+    /// First packet of the Port-Address
+    First,
+    /// Packet following the previous one
+    InOrder,
+    /// Packet following the previous one after the given number of missing packets
+    Dropped(u8),
+    /// Packet older than (or repeating) the previous one, it should be discarded
+    OutOfOrder,
+    /// Packet following `SEQUENCE_RESYNC` out of order packets, the sender restarted its
+    /// sequence and the tracker follows the new one
+    Restarted,
+}
+
+/// Statistics of a `SequenceTracker`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SequenceStats {
+    pub received: u64,
+    pub dropped: u64,
+    pub out_of_order: u64,
+    pub restarts: u64,
+}
+
+/// Consecutive out of order packets after which the sender is assumed to have restarted
+pub const SEQUENCE_RESYNC: u8 = 3;
+
+/// Receiver side check of the ArtDmx sequence numbers of each Port-Address
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    /// Sequence of the last accepted packet of each Port-Address and the number of out of
+    /// order packets received since
+    sequences: HashMap<u16, (u8, u8)>,
+    stats: SequenceStats,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> &SequenceStats {
+        &self.stats
+    }
+
+    /// Check the sequence of a packet received on `port_address`, sequences more than half
+    /// the sequence range ahead of the last accepted packet are late packets
+    pub fn check(&mut self, port_address: u16, sequence: u8) -> SequenceStatus {
+        self.stats.received += 1;
+        if sequence == 0 {
+            return SequenceStatus::Disabled;
+        }
+        let (last, out_of_order) = match self.sequences.get_mut(&port_address) {
+            Some(entry) => entry,
+            None => {
+                self.sequences.insert(port_address, (sequence, 0));
+                return SequenceStatus::First;
+            }
+        };
+        // Distance in the 1 to 255 sequence range
+        let distance = (sequence as i16 - *last as i16).rem_euclid(255);
+        match distance {
+            1..=127 => {
+                *last = sequence;
+                *out_of_order = 0;
+                if distance == 1 {
+                    return SequenceStatus::InOrder;
+                }
+                self.stats.dropped += distance as u64 - 1;
+                SequenceStatus::Dropped(distance as u8 - 1)
+            }
+            // A late packet is followed by newer ones, a restarted sender isn't
+            _ if *out_of_order + 1 >= SEQUENCE_RESYNC => {
+                *last = sequence;
+                *out_of_order = 0;
+                self.stats.restarts += 1;
+                SequenceStatus::Restarted
+            }
+            _ => {
+                *out_of_order += 1;
+                self.stats.out_of_order += 1;
+                SequenceStatus::OutOfOrder
+            }
+        }
+    }
+
+    /// Check the sequence of a received ArtDmx packet
+    pub fn check_art_dmx<B: AsRef<[u8]>>(&mut self, packet: &ArtDmx<B>) -> SequenceStatus {
+        self.check(packet.port_address(), packet.sequence())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmx::ART_DMX_MAX_LENGTH;
+    use crate::encoder::serialize_packets;
+    use std::convert::TryFrom;
+
+    fn opt(color_mode: ColorMode, channels: Option<Vec<Channel>>) -> MappingOptExt {
+        opt_with_depth(color_mode, channels, ColorDepth::Bit8)
+    }
+
+    fn opt_with_depth(
+        color_mode: ColorMode,
+        channels: Option<Vec<Channel>>,
+        color_depth: ColorDepth,
+    ) -> MappingOptExt {
+        MappingOptExt::try_from(MappingOpt {
+            dmx_size: 400,
+            width: 20,
+            height: 10,
+            univer_height: 10,
+            color_mode,
+            displacement: Displacement::Progressive,
+            direction: Direction::Vertical,
+            orientation: vec![Orientation::TopLeft; 4],
+            start_address: 0,
+            port_addresses: Some(vec![0x1234, 0x0042, 0x0043, 0x0044]),
+            channels,
+            color_depth,
+            correction: CorrectionOpt::default(),
+            power: PowerOpt::default(),
+            dithering: false,
+            pacing: PacingOpt::default(),
+            nodes: None,
+            protocol: Protocol::Artnet,
+            artnet: ArtnetOpt::default(),
+        })
+        .unwrap()
+    }
+
+    fn encode(opt: MappingOptExt, buffer: &[u8]) -> Vec<u8> {
+        let buffer: Vec<u16> = buffer.iter().cloned().map(upscale).collect();
+        encode_16(opt, &buffer)
+    }
+
+    fn encode_16(opt: MappingOptExt, buffer: &[u16]) -> Vec<u8> {
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let mut encoder = ArtnetEncoder::new(opt);
+        encoder.encode(&matrix, buffer)[0].slots().to_vec()
+    }
+
+    #[test]
+    fn encode_rgb_keeps_next_pixel() {
+        let opt = opt(ColorMode::RGB, None);
+        let buffer: Vec<u8> = (0..opt.width * opt.height * 4).map(|i| i as u8).collect();
+        let data = encode(opt.clone(), &buffer);
+        // Pixel (0, 0) then pixel (0, 1) which is one row below in the input buffer
+        let row = opt.width * 4;
+        assert_eq!(
+            &data[0..6],
+            &[0, 1, 2, row as u8, row as u8 + 1, row as u8 + 2]
+        );
+    }
+
+    #[test]
+    fn encode_native_order() {
+        let opt = opt(ColorMode::WRGB, None);
+        let buffer = [40, 50, 60, 255].repeat(opt.width * opt.height);
+        assert_eq!(
+            &encode(opt, &buffer)[0..8],
+            &[40, 40, 50, 60, 40, 40, 50, 60]
+        );
+    }
+
+    #[test]
+    fn encode_rgba_keeps_fourth_channel() {
+        let opt = opt(ColorMode::RGBA, None);
+        let buffer = vec![0xAA; opt.width * opt.height * 4];
+        assert_eq!(&encode(opt, &buffer)[0..4], &[0xAA; 4]);
+    }
+
+    #[test]
+    fn encode_with_channel_transform() {
+        let opt = opt(
+            ColorMode::RGBA,
+            Some(vec![Channel::Blue, Channel::Red, Channel::White]),
+        );
+        let buffer = [40, 50, 60, 255].repeat(opt.width * opt.height);
+        assert_eq!(&encode(opt, &buffer)[0..6], &[60, 40, 40, 60, 40, 40]);
+    }
+
+    #[test]
+    fn encode_coarse_fine() {
+        let opt = opt_with_depth(ColorMode::RGB, None, ColorDepth::Bit16);
+        let buffer = [0x1234, 0x5678, 0x9ABC, 0xFFFF].repeat(opt.width * opt.height);
+        assert_eq!(
+            &encode_16(opt.clone(), &buffer)[0..6],
+            &[0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC]
+        );
+        // 8 bits input are upscaled to the full 16 bits range
+        let buffer = [0xFF, 0x00, 0x80, 0xFF].repeat(opt.width * opt.height);
+        assert_eq!(
+            &encode(opt, &buffer)[0..6],
+            &[0xFF, 0xFF, 0x00, 0x00, 0x80, 0x80]
+        );
+    }
+
+    #[test]
+    fn port_address_split_into_net_and_sub_uni() {
+        let opt = opt(ColorMode::RGBA, None);
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        let buffer = vec![0; opt.width * opt.height * 4];
+        let univers = encoder.encode(&matrix, &buffer);
+        assert_eq!(univers[0].port_address(), 0x1234);
+        let packet = &serialize_packets(&mut ArtnetSerializer::default(), &univers[0])[0];
+        // SubUni then Net
+        assert_eq!(&packet[14..16], &[0x34, 0x12]);
+        let packet = &serialize_packets(&mut ArtnetSerializer::default(), &univers[1])[0];
+        assert_eq!(&packet[14..16], &[0x42, 0x00]);
+    }
+
+    #[test]
+    fn serialize_art_dmx() {
+        let univer = UniverBuffer::with_slots(0x0042, &[1, 2, 3, 4]);
+        let packet = &serialize_packets(&mut ArtnetSerializer::default(), &univer)[0];
+        assert_eq!(&packet[0..8], b"Art-Net\0");
+        match ArtCommand::from_buffer(packet).unwrap() {
+            ArtCommand::Output(output) => assert_eq!(output.data, vec![1, 2, 3, 4]),
+            _ => panic!("expected an ArtDmx packet"),
+        }
+    }
+
+    #[test]
+    fn serialize_golden_art_dmx() {
+        let mut univer = UniverBuffer::with_slots(0x7FFF, &[0xFF, 0x80, 0x01]);
+        univer.set_sequence(1);
+        let mut serializer = ArtnetSerializer::default();
+        // The odd univer is padded to an even length
+        #[rustfmt::skip]
+        let expected = vec![
+            b'A', b'r', b't', b'-', b'N', b'e', b't', 0,
+            0x00, 0x50, 0, 14, 1, 0, 0xFF, 0x7F, 0, 4,
+            0xFF, 0x80, 0x01, 0,
+        ];
+        assert_eq!(serialize_packets(&mut serializer, &univer), vec![expected]);
+        // The slots are written in place in the packet
+        univer.slots_mut()[2] = 0x10;
+        let packet = &serialize_packets(&mut serializer, &univer)[0];
+        assert_eq!(&packet[16..], &[0, 4, 0xFF, 0x80, 0x10, 0]);
+    }
+
+    #[test]
+    fn encoder_fills_art_dmx_data() {
+        let opt = opt(ColorMode::RGBA, None);
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        let buffer = [0xFF, 0x00, 0x80, 0xFF].repeat(opt.width * opt.height);
+        let buffer: Vec<u16> = buffer.iter().cloned().map(upscale).collect();
+        let univer = &encoder.encode(&matrix, &buffer)[0];
+        let packet = univer.art_dmx().unwrap();
+        assert_eq!(packet.data(), univer.slots());
+        assert_eq!((packet.port_address(), packet.sequence()), (0x1234, 1));
+        assert_eq!(&packet.data()[0..4], &[0xFF, 0x00, 0x80, 0xFF]);
+    }
+
+    #[test]
+    fn oversized_univer_is_rejected() {
+        let univer = UniverBuffer::with_slots(0, &[0; ART_DMX_MAX_LENGTH + 1]);
+        let mut send = |_: &[u8]| Ok(());
+        match ArtnetSerializer::default().serialize(&univer, &mut send) {
+            Err(GError::UniverTooLarge { dmx_size, max }) => {
+                assert_eq!(
+                    (dmx_size, max),
+                    (ART_DMX_MAX_LENGTH + 1, ART_DMX_MAX_LENGTH)
+                )
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    /// Packets of a frame sent to `nodes`
+    fn sync_frame(sync: bool, nodes: Vec<SocketAddr>) -> Vec<(SocketAddr, ArtCommand)> {
+        let opt = opt(ColorMode::RGBA, None);
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        let transport = LoopbackTransport::new();
+        let serializer = ArtnetSerializer::new(&ArtnetOpt {
+            sync,
+            ..ArtnetOpt::default()
+        });
+        let mut router = Router::new(serializer, transport.clone(), nodes);
+        router
+            .send(encoder.encode(&matrix, &vec![0; opt.width * opt.height * 4]))
+            .unwrap();
+        transport
+            .take()
+            .into_iter()
+            .map(|(node, packet)| (node, ArtCommand::from_buffer(&packet).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn art_sync_follows_art_dmx() {
+        let nodes = vec![
+            SocketAddr::from(([10, 0, 0, 1], ARTNET_PORT)),
+            SocketAddr::from(([10, 0, 0, 2], ARTNET_PORT)),
+        ];
+        let packets = sync_frame(true, nodes.clone());
+        assert_eq!(packets.len(), 4);
+        for (i, (node, command)) in packets.iter().enumerate() {
+            assert_eq!(*node, nodes[i % 2]);
+            match (i < 2, command) {
+                (true, ArtCommand::Output(_)) | (false, ArtCommand::Sync(_)) => {}
+                _ => panic!("unexpected packet {} {:?}", i, command),
+            }
+        }
+        // A single ArtSync per node
+        let packets = sync_frame(true, vec![nodes[0]; 2]);
+        assert_eq!(packets.len(), 3);
+    }
+
+    #[test]
+    fn art_sync_fallback() {
+        let node = SocketAddr::from(([10, 0, 0, 1], ARTNET_PORT));
+        assert_eq!(sync_frame(false, vec![node; 2]).len(), 2);
+        let broadcast = SocketAddr::from(([255, 255, 255, 255], ARTNET_PORT));
+        assert_eq!(sync_frame(true, vec![node, broadcast]).len(), 2);
+        // A node of a wider subnet can have an address ending with 255
+        let node = SocketAddr::from(([10, 0, 1, 255], ARTNET_PORT));
+        assert_eq!(sync_frame(true, vec![node; 2]).len(), 3);
+    }
+
+    #[test]
+    fn sequence_per_port_address() {
+        let sequences = |protocol, artnet, port_addresses| {
+            let opt = MappingOptExt {
+                protocol,
+                artnet,
+                port_addresses,
+                ..opt(ColorMode::RGBA, None)
+            };
+            let matrix = AddrMap::from_mapping(opt.clone());
+            let mut encoder = ArtnetEncoder::new(opt.clone());
+            let buffer = vec![0; opt.width * opt.height * 4];
+            (0..256)
+                .map(|_| {
+                    encoder
+                        .encode(&matrix, &buffer)
+                        .iter()
+                        .map(|univer| univer.sequence())
+                        .collect()
+                })
+                .collect::<Vec<Vec<u8>>>()
+        };
+        let numbered = sequences(Protocol::Artnet, ArtnetOpt::default(), vec![0x42, 0x43]);
+        assert_eq!(numbered[0], vec![1, 1]);
+        assert_eq!(numbered[254], vec![255, 255]);
+        // 0 is reserved for disabled sequences
+        assert_eq!(numbered[255], vec![1, 1]);
+        // Both univers share the same Port-Address
+        let shared = sequences(Protocol::Artnet, ArtnetOpt::default(), vec![0x42, 0x42]);
+        assert_eq!(shared[0], vec![1, 2]);
+        assert_eq!(shared[127], vec![255, 1]);
+        let disabled = ArtnetOpt {
+            sequence: false,
+            ..ArtnetOpt::default()
+        };
+        let unnumbered = sequences(Protocol::Artnet, disabled.clone(), vec![0x42, 0x43]);
+        assert_eq!(unnumbered[1], vec![0, 0]);
+        // sACN receivers expect a sequence whatever the Art-Net options
+        let e131 = sequences(
+            Protocol::E131(E131Opt::default()),
+            disabled,
+            vec![0x42, 0x43],
+        );
+        assert_eq!(e131[1], vec![2, 2]);
+    }
+
+    #[test]
+    fn sequence_tracker() {
+        use SequenceStatus::*;
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.check(1, 0), Disabled);
+        assert_eq!(tracker.check(1, 254), First);
+        assert_eq!(tracker.check(1, 255), InOrder);
+        assert_eq!(tracker.check(1, 1), InOrder);
+        assert_eq!(tracker.check(1, 4), Dropped(2));
+        assert_eq!(tracker.check(1, 3), OutOfOrder);
+        assert_eq!(tracker.check(1, 4), OutOfOrder);
+        assert_eq!(tracker.check(1, 5), InOrder);
+        assert_eq!(tracker.check(2, 5), First);
+        assert_eq!(
+            tracker.stats(),
+            &SequenceStats {
+                received: 9,
+                dropped: 2,
+                out_of_order: 2,
+                restarts: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn sequence_tracker_follows_restarted_sender() {
+        use SequenceStatus::*;
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.check(1, 100), First);
+        // The sender restarts from 1
+        assert_eq!(tracker.check(1, 1), OutOfOrder);
+        assert_eq!(tracker.check(1, 2), OutOfOrder);
+        assert_eq!(tracker.check(1, 3), Restarted);
+        assert_eq!(tracker.check(1, 4), InOrder);
+        // A late packet doesn't resynchronise the tracker
+        assert_eq!(tracker.check(1, 2), OutOfOrder);
+        assert_eq!(tracker.check(1, 5), InOrder);
+        assert_eq!(tracker.stats().restarts, 1);
+        assert_eq!(tracker.stats().out_of_order, 3);
+    }
+
+    #[test]
+    fn sequence_tracker_reads_art_dmx() {
+        let opt = opt(ColorMode::RGBA, None);
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        let buffer = vec![0; opt.width * opt.height * 4];
+        let mut serializer = ArtnetSerializer::default();
+        let mut tracker = SequenceTracker::new();
+        let mut statuses = vec![];
+        for _ in 0..2 {
+            for univer in encoder.encode(&matrix, &buffer) {
+                let packet = &serialize_packets(&mut serializer, univer)[0];
+                statuses.push(tracker.check_art_dmx(&ArtDmx::new_checked(packet).unwrap()));
+            }
+        }
+        use SequenceStatus::*;
+        assert_eq!(statuses, vec![First, First, InOrder, InOrder]);
+    }
+}
@@ -0,0 +1,317 @@
+//!
+//! Distributed Display Protocol packets
+//! Based on http://www.3waylabs.com/ddp/
+//!
+//! DDP has no univer size limit, univers are reinterpreted as output ports: the univers
+//! sent to the same node are concatenated (in univer order) into a single pixel stream
+//!
+
+use super::{Serializer, UniverBuffer};
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// DDP UDP port
+pub const DDP_PORT: u16 = 4048;
+/// Maximum data length of a packet (480 RGB pixels)
+pub const DDP_MAX_DATA: usize = 1440;
+
+const HEADER_SIZE: usize = 10;
+const FLAG_VERSION_1: u8 = 0x40;
+const FLAG_PUSH: u8 = 0x01;
+/// Default output device of the node
+const DEFAULT_DESTINATION: u8 = 0x01;
+
+/// Serialize univers into DDP packets
+#[derive(Debug, Clone)]
+pub struct DdpSerializer {
+    data_type: u8,
+    /// Data length of a fragment, a multiple of the pixel size
+    fragment_size: usize,
+    /// Node of each univer of the current frame, given by the `Router`
+    nodes: Vec<SocketAddr>,
+    /// Offset (bytes) of the next univer in the pixel stream of each node
+    offsets: HashMap<SocketAddr, usize>,
+    /// Univer serialized next in the current frame
+    next_univer: usize,
+    /// Reused serialization buffer
+    packet: Vec<u8>,
+}
+
+/// Data type field (pixel layout and bits per channel)
+fn data_type(opt: &MappingOptExt) -> u8 {
+    use Channel::*;
+    let layout = match opt.channels.as_slice() {
+        [Red, Green, Blue] => 1,
+        [Red, Green, Blue, White] => 3,
+        [White] | [Luminance] => 4,
+        _ => 0,
+    };
+    let size = match opt.color_depth {
+        ColorDepth::Bit8 => 3,
+        ColorDepth::Bit16 => 4,
+    };
+    (layout << 3) | size
+}
+
+impl DdpSerializer {
+    pub fn new(opt: &MappingOptExt) -> Self {
+        Self {
+            data_type: data_type(opt),
+            fragment_size: DDP_MAX_DATA - DDP_MAX_DATA % opt.pixel_size,
+            nodes: vec![],
+            offsets: HashMap::new(),
+            next_univer: 0,
+            packet: vec![],
+        }
+    }
+}
+
+impl Serializer for DdpSerializer {
+    fn begin_frame(&mut self, nodes: &[SocketAddr]) {
+        self.nodes.clear();
+        self.nodes.extend_from_slice(nodes);
+        self.offsets.clear();
+        self.next_univer = 0;
+    }
+
+    fn serialize(
+        &mut self,
+        univer: &UniverBuffer,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        let node = *self
+            .nodes
+            .get(self.next_univer)
+            .ok_or(GError::InvalidPacket(
+                "DDP frame has more univers than nodes",
+            ))?;
+        // The last univer sent to a node ends its pixel stream
+        let last = !self.nodes[self.next_univer + 1..].contains(&node);
+        self.next_univer += 1;
+        let next_offset = self.offsets.entry(node).or_insert(0);
+        let start = *next_offset;
+        *next_offset += univer.slots().len();
+        let fragments = univer.slots().len().div_ceil(self.fragment_size);
+        // DDP sequence numbers go from 1 to 15, 0 when not numbered
+        let sequence = match univer.sequence() {
+            0 => 0,
+            sequence => (sequence - 1) % 15 + 1,
+        };
+        for (i, data) in univer.slots().chunks(self.fragment_size).enumerate() {
+            let push = last && i + 1 == fragments;
+            let offset = (start + i * self.fragment_size) as u32;
+            self.packet.clear();
+            self.packet
+                .push(FLAG_VERSION_1 | if push { FLAG_PUSH } else { 0 });
+            self.packet.push(sequence);
+            self.packet.push(self.data_type);
+            self.packet.push(DEFAULT_DESTINATION);
+            self.packet.extend_from_slice(&offset.to_be_bytes());
+            self.packet
+                .extend_from_slice(&(data.len() as u16).to_be_bytes());
+            self.packet.extend_from_slice(data);
+            send(&self.packet)?;
+        }
+        Ok(())
+    }
+}
+
+/// Header of a DDP packet
+#[derive(Debug, Clone, PartialEq)]
+pub struct DdpHeader {
+    pub push: bool,
+    pub sequence: u8,
+    pub data_type: u8,
+    pub destination: u8,
+    pub offset: u32,
+    pub length: u16,
+}
+
+impl DdpHeader {
+    /// Parse the header of a packet, return the header and the packet data
+    pub fn parse(packet: &[u8]) -> Result<(Self, &[u8]), GError> {
+        if packet.len() < HEADER_SIZE {
+            return Err(GError::InvalidPacket("DDP packet too short"));
+        }
+        if packet[0] & 0xC0 != FLAG_VERSION_1 {
+            return Err(GError::InvalidPacket("unsupported DDP version"));
+        }
+        let header = Self {
+            push: packet[0] & FLAG_PUSH != 0,
+            sequence: packet[1] & 0x0F,
+            data_type: packet[2],
+            destination: packet[3],
+            offset: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            length: u16::from_be_bytes([packet[8], packet[9]]),
+        };
+        let data = &packet[HEADER_SIZE..];
+        let length = header.length as usize;
+        if data.len() < length {
+            return Err(GError::InvalidPacket("DDP data shorter than its length"));
+        }
+        Ok((header, &data[..length]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    /// Matrix of 4 univers of 600 RGB pixels (1800 bytes), the first three on the same node
+    fn opt() -> MappingOptExt {
+        let node = SocketAddr::from(([10, 0, 0, 1], DDP_PORT));
+        let other = SocketAddr::from(([10, 0, 0, 2], DDP_PORT));
+        MappingOptExt::try_from(MappingOpt {
+            dmx_size: 1800,
+            width: 40,
+            height: 60,
+            univer_height: 60,
+            color_mode: ColorMode::RGB,
+            displacement: Displacement::Progressive,
+            direction: Direction::Vertical,
+            orientation: vec![Orientation::TopLeft; 4],
+            start_address: 0,
+            port_addresses: None,
+            channels: None,
+            color_depth: ColorDepth::Bit8,
+            correction: CorrectionOpt::default(),
+            power: PowerOpt::default(),
+            dithering: false,
+            pacing: PacingOpt::default(),
+            nodes: Some(vec![node, node, node, other]),
+            protocol: Protocol::Ddp,
+            artnet: ArtnetOpt::default(),
+        })
+        .unwrap()
+    }
+
+    /// Headers of the packets of a frame
+    fn frame(
+        serializer: &mut DdpSerializer,
+        encoder: &mut ArtnetEncoder,
+        opt: &MappingOptExt,
+    ) -> Vec<Vec<DdpHeader>> {
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let buffer = vec![0; opt.width * opt.height * 4];
+        serializer.begin_frame(&opt.nodes);
+        encoder
+            .encode(&matrix, &buffer)
+            .iter()
+            .map(|univer| {
+                let mut headers = vec![];
+                serializer
+                    .serialize(univer, &mut |packet| {
+                        headers.push(DdpHeader::parse(packet)?.0);
+                        Ok(())
+                    })
+                    .unwrap();
+                headers
+            })
+            .collect()
+    }
+
+    #[test]
+    fn univers_bypass_dmx_size() {
+        let opt = opt();
+        assert_eq!(opt.univer_width, 10);
+        assert_eq!(opt.univer_per_column, 4);
+    }
+
+    #[test]
+    fn fragments_offsets_and_push() {
+        let opt = opt();
+        let mut serializer = DdpSerializer::new(&opt);
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        let univers = frame(&mut serializer, &mut encoder, &opt);
+        let offsets: Vec<Vec<(u32, u16, bool)>> = univers
+            .iter()
+            .map(|headers| {
+                headers
+                    .iter()
+                    .map(|header| (header.offset, header.length, header.push))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            offsets,
+            vec![
+                vec![(0, 1440, false), (1440, 360, false)],
+                vec![(1800, 1440, false), (3240, 360, false)],
+                vec![(3600, 1440, false), (5040, 360, true)],
+                vec![(0, 1440, false), (1440, 360, true)],
+            ]
+        );
+        assert_eq!(univers[0][0].data_type, 0x0B);
+        assert_eq!(univers[0][0].destination, 1);
+    }
+
+    #[test]
+    fn layout_follows_router_nodes() {
+        let opt = opt();
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        let node = SocketAddr::from(([10, 0, 0, 3], DDP_PORT));
+        let transport = LoopbackTransport::new();
+        // Every univer is sent to a single node instead of the configured ones
+        let mut router = Router::new(DdpSerializer::new(&opt), transport.clone(), vec![node; 4]);
+        router
+            .send(encoder.encode(&matrix, &vec![0; opt.width * opt.height * 4]))
+            .unwrap();
+        let headers: Vec<(u32, bool)> = transport
+            .take()
+            .iter()
+            .map(|(_, packet)| DdpHeader::parse(packet).unwrap().0)
+            .map(|header| (header.offset, header.push))
+            .collect();
+        assert_eq!(headers.len(), 8);
+        assert_eq!(headers[2], (1800, false));
+        assert_eq!(headers[7], (5400 + 1440, true));
+        assert_eq!(headers.iter().filter(|(_, push)| *push).count(), 1);
+    }
+
+    #[test]
+    fn sequence_per_frame() {
+        let opt = opt();
+        let mut serializer = DdpSerializer::new(&opt);
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        assert!(frame(&mut serializer, &mut encoder, &opt)
+            .iter()
+            .flatten()
+            .all(|header| header.sequence == 1));
+        for _ in 0..14 {
+            frame(&mut serializer, &mut encoder, &opt);
+        }
+        // The univer sequence wraps within the 4 bits of the header
+        assert_eq!(frame(&mut serializer, &mut encoder, &opt)[0][0].sequence, 1);
+        let univer = UniverBuffer::with_slots(0, &[0; 3]);
+        serializer.begin_frame(&opt.nodes);
+        serializer
+            .serialize(&univer, &mut |packet| {
+                assert_eq!(DdpHeader::parse(packet)?.0.sequence, 0);
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn fragments_hold_whole_pixels() {
+        let mut opt = opt();
+        opt.pixel_size = 4;
+        assert_eq!(DdpSerializer::new(&opt).fragment_size, 1440);
+        opt.pixel_size = 6;
+        assert_eq!(DdpSerializer::new(&opt).fragment_size, 1440);
+        opt.pixel_size = 7;
+        assert_eq!(DdpSerializer::new(&opt).fragment_size, 1435);
+    }
+
+    #[test]
+    fn parse_rejects_invalid_packets() {
+        assert!(DdpHeader::parse(&[0x40, 1, 0x0B, 1, 0, 0]).is_err());
+        assert!(DdpHeader::parse(&[0x80, 1, 0x0B, 1, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(DdpHeader::parse(&[0x41, 1, 0x0B, 1, 0, 0, 0, 0, 0, 2, 0xFF]).is_err());
+        let (header, data) = DdpHeader::parse(&[0x41, 1, 0x0B, 1, 0, 0, 0, 3, 0, 1, 0xFF]).unwrap();
+        assert_eq!((header.push, header.offset, data), (true, 3, &[0xFF][..]));
+    }
+}
@@ -0,0 +1,467 @@
+//!
+//! Streaming ACN data packets
+//! Based on ANSI E1.31-2016 (https://tsp.esta.org/tsp/documents/docs/ANSI_E1-31-2018.pdf)
+//!
+
+use super::{Serializer, UniverBuffer};
+use crate::prelude::*;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{Ipv4Addr, SocketAddr};
+
+/// sACN UDP port
+pub const E131_PORT: u16 = 5568;
+/// Highest sACN univer number
+pub const MAX_E131_UNIVERSE: u16 = 63999;
+
+const ACN_PACKET_IDENTIFIER: [u8; 12] = *b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+/// Offset of the framing layer, the DMP layer and the DMX start code
+const FRAMING_OFFSET: usize = 38;
+const DMP_OFFSET: usize = 115;
+const START_CODE_OFFSET: usize = 125;
+const SOURCE_NAME_SIZE: usize = 64;
+const OPTION_PREVIEW: u8 = 0x80;
+const OPTION_STREAM_TERMINATED: u8 = 0x40;
+/// Packets sent with the Stream_Terminated option when a source stops
+const TERMINATED_PACKETS: u8 = 3;
+
+impl E131Opt {
+    pub fn validate(&self) -> Result<(), GError> {
+        if self.priority > 200 {
+            return Err(GError::InvalidPriority {
+                priority: self.priority,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// sACN univer of an univer buffer (Port-Address 0 is univer 1)
+pub fn e131_universe(univer: &UniverBuffer) -> u16 {
+    univer.port_address() + 1
+}
+
+/// Multicast address of a sACN univer (239.255.{high byte}.{low byte})
+pub fn e131_multicast_addr(universe: u16) -> SocketAddr {
+    let [high, low] = universe.to_be_bytes();
+    SocketAddr::from((Ipv4Addr::new(239, 255, high, low), E131_PORT))
+}
+
+/// Random version 4 UUID
+fn generate_cid() -> [u8; 16] {
+    let mut cid = [0; 16];
+    for chunk in cid.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|time| time.as_nanos())
+                .unwrap_or(0),
+        );
+        chunk.copy_from_slice(&hasher.finish().to_be_bytes());
+    }
+    cid[6] = (cid[6] & 0x0F) | 0x40;
+    cid[8] = (cid[8] & 0x3F) | 0x80;
+    cid
+}
+
+/// Serialize univers into sACN data packets
+#[derive(Debug, Clone)]
+pub struct E131Serializer {
+    cid: [u8; 16],
+    source_name: [u8; SOURCE_NAME_SIZE],
+    priority: u8,
+    options: u8,
+    /// Reused serialization buffer
+    packet: Vec<u8>,
+}
+
+impl E131Serializer {
+    pub fn new(opt: &E131Opt) -> Result<Self, GError> {
+        opt.validate()?;
+        // The name is null terminated, longer names are truncated
+        let mut source_name = [0; SOURCE_NAME_SIZE];
+        let name = opt.source_name.as_bytes();
+        let len = std::cmp::min(name.len(), SOURCE_NAME_SIZE - 1);
+        source_name[..len].copy_from_slice(&name[..len]);
+        Ok(Self {
+            cid: opt.cid.unwrap_or_else(generate_cid),
+            source_name,
+            priority: opt.priority,
+            options: if opt.preview { OPTION_PREVIEW } else { 0 },
+            packet: vec![],
+        })
+    }
+
+    pub fn cid(&self) -> [u8; 16] {
+        self.cid
+    }
+
+    /// Mark the next packets as the last ones of the stream, receivers stop
+    /// waiting for data instead of holding the last frame (`Router::terminate` sends the
+    /// three terminated packets of each univer asked by the spec)
+    pub fn set_stream_terminated(&mut self, terminated: bool) {
+        if terminated {
+            self.options |= OPTION_STREAM_TERMINATED;
+        } else {
+            self.options &= !OPTION_STREAM_TERMINATED;
+        }
+    }
+}
+
+/// Flags (0x7) and length of a PDU starting at `offset` in a packet of `len` bytes
+fn flags_and_length(offset: usize, len: usize) -> [u8; 2] {
+    (0x7000 | (len - offset) as u16).to_be_bytes()
+}
+
+impl E131Serializer {
+    /// Data packet of `univer` with the given sequence number and options
+    fn write(
+        &mut self,
+        univer: &UniverBuffer,
+        sequence: u8,
+        options: u8,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        let universe = e131_universe(univer);
+        if universe > MAX_E131_UNIVERSE {
+            return Err(GError::InvalidPortAddress {
+                address: univer.port_address() as usize,
+            });
+        }
+        let len = START_CODE_OFFSET + 1 + univer.slots().len();
+        let mut packet = std::mem::take(&mut self.packet);
+        packet.clear();
+        // Root layer
+        packet.extend_from_slice(&0x0010u16.to_be_bytes());
+        packet.extend_from_slice(&0x0000u16.to_be_bytes());
+        packet.extend_from_slice(&ACN_PACKET_IDENTIFIER);
+        packet.extend_from_slice(&flags_and_length(16, len));
+        packet.extend_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+        packet.extend_from_slice(&self.cid);
+        // Framing layer
+        packet.extend_from_slice(&flags_and_length(FRAMING_OFFSET, len));
+        packet.extend_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+        packet.extend_from_slice(&self.source_name);
+        packet.push(self.priority);
+        // Synchronization address, not used
+        packet.extend_from_slice(&0u16.to_be_bytes());
+        packet.push(sequence);
+        packet.push(options);
+        packet.extend_from_slice(&universe.to_be_bytes());
+        // DMP layer
+        packet.extend_from_slice(&flags_and_length(DMP_OFFSET, len));
+        packet.push(VECTOR_DMP_SET_PROPERTY);
+        // Address type and data type
+        packet.push(0xA1);
+        // First property address and address increment
+        packet.extend_from_slice(&0u16.to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes());
+        packet.extend_from_slice(&(univer.slots().len() as u16 + 1).to_be_bytes());
+        // DMX512 null start code
+        packet.push(0);
+        packet.extend_from_slice(univer.slots());
+        let result = send(&packet);
+        self.packet = packet;
+        result
+    }
+}
+
+impl Serializer for E131Serializer {
+    fn serialize(
+        &mut self,
+        univer: &UniverBuffer,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        self.write(univer, univer.sequence(), self.options, send)
+    }
+
+    /// Three packets with the Stream_Terminated option, numbered from the univer sequence
+    fn terminate(
+        &mut self,
+        univer: &UniverBuffer,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        let options = self.options | OPTION_STREAM_TERMINATED;
+        for i in 0..TERMINATED_PACKETS {
+            self.write(univer, univer.sequence().wrapping_add(i), options, send)?;
+        }
+        Ok(())
+    }
+}
+
+/// Content of a parsed sACN data packet
+#[derive(Debug, Clone, PartialEq)]
+pub struct E131Data {
+    pub cid: [u8; 16],
+    pub source_name: String,
+    pub priority: u8,
+    pub sequence: u8,
+    pub preview: bool,
+    pub stream_terminated: bool,
+    pub universe: u16,
+    pub slots: Vec<u8>,
+}
+
+impl E131Data {
+    pub fn parse(packet: &[u8]) -> Result<Self, GError> {
+        let u16_at = |offset: usize| u16::from_be_bytes([packet[offset], packet[offset + 1]]);
+        let u32_at = |offset: usize| {
+            u32::from_be_bytes([
+                packet[offset],
+                packet[offset + 1],
+                packet[offset + 2],
+                packet[offset + 3],
+            ])
+        };
+        if packet.len() <= START_CODE_OFFSET {
+            return Err(GError::InvalidPacket("sACN packet too short"));
+        }
+        if packet[4..16] != ACN_PACKET_IDENTIFIER {
+            return Err(GError::InvalidPacket("not an ACN packet"));
+        }
+        if u32_at(18) != VECTOR_ROOT_E131_DATA || u32_at(40) != VECTOR_E131_DATA_PACKET {
+            return Err(GError::InvalidPacket("not a sACN data packet"));
+        }
+        if packet[117] != VECTOR_DMP_SET_PROPERTY || packet[118] != 0xA1 {
+            return Err(GError::InvalidPacket("invalid sACN DMP layer"));
+        }
+        let count = u16_at(123) as usize;
+        if count == 0 || START_CODE_OFFSET + count > packet.len() {
+            return Err(GError::InvalidPacket("invalid sACN property count"));
+        }
+        if usize::from(u16_at(DMP_OFFSET) & 0x0FFF) != START_CODE_OFFSET + count - DMP_OFFSET {
+            return Err(GError::InvalidPacket("invalid sACN DMP layer length"));
+        }
+        let mut cid = [0; 16];
+        cid.copy_from_slice(&packet[22..38]);
+        let name = &packet[44..44 + SOURCE_NAME_SIZE];
+        let name_len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        Ok(Self {
+            cid,
+            source_name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+            priority: packet[108],
+            sequence: packet[111],
+            preview: packet[112] & OPTION_PREVIEW != 0,
+            stream_terminated: packet[112] & OPTION_STREAM_TERMINATED != 0,
+            universe: u16_at(113),
+            slots: packet[START_CODE_OFFSET + 1..START_CODE_OFFSET + count].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::serialize_packets;
+    use std::net::UdpSocket;
+
+    const CID: [u8; 16] = [
+        0xEF, 0x07, 0xC8, 0xDD, 0x00, 0x64, 0x44, 0x01, 0xA3, 0xA2, 0x45, 0x9E, 0xF8, 0xE6, 0x14,
+        0x3E,
+    ];
+
+    /// Data packet of univer 1 with 4 slots, source "glola", priority 100 and sequence 1,
+    /// written field by field from the E1.31-2016 packet format (table 4-1) rather than
+    /// produced by the serializer
+    #[rustfmt::skip]
+    const REFERENCE: [u8; 130] = [
+        // Root layer
+        0x00, 0x10,             // Preamble size
+        0x00, 0x00,             // Post-amble size
+        0x41, 0x53, 0x43, 0x2D, 0x45, 0x31, 0x2E, 0x31, 0x37, 0x00, 0x00, 0x00, // ACN packet identifier
+        0x70, 0x72,             // Flags and length (130 - 16)
+        0x00, 0x00, 0x00, 0x04, // VECTOR_ROOT_E131_DATA
+        0xEF, 0x07, 0xC8, 0xDD, 0x00, 0x64, 0x44, 0x01,
+        0xA3, 0xA2, 0x45, 0x9E, 0xF8, 0xE6, 0x14, 0x3E, // CID
+
+        // Framing layer
+        0x70, 0x5C,             // Flags and length (130 - 38)
+        0x00, 0x00, 0x00, 0x02, // VECTOR_E131_DATA_PACKET
+        b'g', b'l', b'o', b'l', b'a', 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Source name
+        0x64,                   // Priority
+        0x00, 0x00,             // Synchronization address
+        0x01,                   // Sequence number
+        0x00,                   // Options
+        0x00, 0x01,             // Universe
+
+        // DMP layer
+        0x70, 0x0F,             // Flags and length (130 - 115)
+        0x02,                   // VECTOR_DMP_SET_PROPERTY
+        0xA1,                   // Address type and data type
+        0x00, 0x00,             // First property address
+        0x00, 0x01,             // Address increment
+        0x00, 0x05,             // Property value count (start code and 4 slots)
+        0x00,                   // DMX start code
+        0x01, 0x02, 0x03, 0x04, // Slots
+    ];
+
+    fn serializer() -> E131Serializer {
+        E131Serializer::new(&E131Opt {
+            cid: Some(CID),
+            source_name: String::from("glola"),
+            priority: 100,
+            preview: false,
+        })
+        .unwrap()
+    }
+
+    fn univer(port_address: u16, slots: Vec<u8>) -> UniverBuffer {
+        let mut univer = UniverBuffer::with_slots(port_address, &slots);
+        univer.set_sequence(1);
+        univer
+    }
+
+    #[test]
+    fn serialize_matches_reference() {
+        let packets = serialize_packets(&mut serializer(), &univer(0, vec![1, 2, 3, 4]));
+        assert_eq!(packets, vec![REFERENCE.to_vec()]);
+    }
+
+    #[test]
+    fn parse_reference() {
+        let data = E131Data::parse(&REFERENCE).unwrap();
+        assert_eq!(
+            data,
+            E131Data {
+                cid: CID,
+                source_name: String::from("glola"),
+                priority: 100,
+                sequence: 1,
+                preview: false,
+                stream_terminated: false,
+                universe: 1,
+                slots: vec![1, 2, 3, 4],
+            }
+        );
+        assert!(E131Data::parse(&REFERENCE[..100]).is_err());
+    }
+
+    #[test]
+    fn full_univer_packet_size() {
+        let packet = serialize_packets(&mut serializer(), &univer(0, vec![0; 512])).remove(0);
+        assert_eq!(packet.len(), 638);
+        assert_eq!(E131Data::parse(&packet).unwrap().slots.len(), 512);
+    }
+
+    #[test]
+    fn sequence_from_univer() {
+        let mut serializer = serializer();
+        for &sequence in &[1, 2, 255] {
+            let mut univer = univer(0, vec![0; 2]);
+            univer.set_sequence(sequence);
+            let packet = serialize_packets(&mut serializer, &univer).remove(0);
+            assert_eq!(E131Data::parse(&packet).unwrap().sequence, sequence);
+        }
+    }
+
+    #[test]
+    fn stream_terminated() {
+        let mut serializer = serializer();
+        serializer.set_stream_terminated(true);
+        let packet = serialize_packets(&mut serializer, &univer(0, vec![0; 2])).remove(0);
+        assert!(E131Data::parse(&packet).unwrap().stream_terminated);
+    }
+
+    #[test]
+    fn terminate_sends_three_packets() {
+        let mut serializer = serializer();
+        let mut univer = univer(0, vec![1, 2]);
+        univer.set_sequence(254);
+        let mut packets = vec![];
+        serializer
+            .terminate(&univer, &mut |packet| {
+                packets.push(E131Data::parse(packet)?);
+                Ok(())
+            })
+            .unwrap();
+        let sequences: Vec<u8> = packets.iter().map(|data| data.sequence).collect();
+        assert_eq!(sequences, vec![254, 255, 0]);
+        assert!(packets.iter().all(|data| data.stream_terminated));
+        assert!(packets.iter().all(|data| data.slots == vec![1, 2]));
+        // The next streams are not terminated
+        let packet = serialize_packets(&mut serializer, &univer).remove(0);
+        assert!(!E131Data::parse(&packet).unwrap().stream_terminated);
+    }
+
+    /// Round trip of a captured sACN data packet: the UDP payload of a packet sent by a
+    /// console or a source such as sACNView, exported from Wireshark with "Export Packet Bytes",
+    /// ex: `E131_CAPTURE=e131.bin cargo test captured -- --ignored`
+    #[test]
+    #[ignore]
+    fn captured_packet_round_trip() {
+        let path = std::env::var("E131_CAPTURE").expect("E131_CAPTURE is not set");
+        let capture = std::fs::read(path).unwrap();
+        let data = E131Data::parse(&capture).unwrap();
+        let mut serializer = E131Serializer::new(&E131Opt {
+            cid: Some(data.cid),
+            source_name: data.source_name.clone(),
+            priority: data.priority,
+            preview: data.preview,
+        })
+        .unwrap();
+        serializer.set_stream_terminated(data.stream_terminated);
+        let mut univer = univer(data.universe - 1, data.slots.clone());
+        univer.set_sequence(data.sequence);
+        let mut packet = serialize_packets(&mut serializer, &univer).remove(0);
+        // glola doesn't synchronize the univers
+        packet[109..111].copy_from_slice(&capture[109..111]);
+        assert_eq!(packet, capture);
+    }
+
+    #[test]
+    fn multicast_address() {
+        assert_eq!(
+            e131_multicast_addr(1),
+            "239.255.0.1:5568".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            e131_multicast_addr(0x1234),
+            "239.255.18.52:5568".parse::<SocketAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn generated_cid() {
+        let opt = E131Opt::default();
+        let (a, b) = (
+            E131Serializer::new(&opt).unwrap(),
+            E131Serializer::new(&opt).unwrap(),
+        );
+        assert_ne!(a.cid(), b.cid());
+        assert_eq!(a.cid()[6] >> 4, 4);
+    }
+
+    #[test]
+    fn invalid_priority() {
+        let opt = E131Opt {
+            priority: 201,
+            ..E131Opt::default()
+        };
+        assert!(E131Serializer::new(&opt).is_err());
+    }
+
+    #[test]
+    fn loopback_udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let node = receiver.local_addr().unwrap();
+        let transport = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let mut router = Router::new(serializer(), transport, vec![node, node]);
+        router
+            .send(&[univer(0, vec![1, 2]), univer(1, vec![3, 4])])
+            .unwrap();
+        let mut buffer = [0; 1024];
+        for (universe, slots) in [(1, vec![1, 2]), (2, vec![3, 4])].iter() {
+            let len = receiver.recv(&mut buffer).unwrap();
+            let data = E131Data::parse(&buffer[..len]).unwrap();
+            assert_eq!(data.universe, *universe);
+            assert_eq!(&data.slots, slots);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//!
+//! Enttec DMX USB Pro messages
+//! Based on the DMX USB Pro Widget API Specification 1.44
+//!
+//! The widget has a single DMX output, univers are sent with the `Output Only Send DMX`
+//! message and the break timing is programmed with the `Set Widget Parameters` message
+//!
+
+use super::{Serializer, UniverBuffer};
+use crate::prelude::*;
+use std::io::Read;
+
+/// Label of the `Set Widget Parameters` message
+pub const ENTTEC_SET_PARAMETERS: u8 = 4;
+/// Label of the `Output Only Send DMX` message
+pub const ENTTEC_SEND_DMX: u8 = 6;
+
+const START_OF_MESSAGE: u8 = 0x7E;
+const END_OF_MESSAGE: u8 = 0xE7;
+const DMX_START_CODE: u8 = 0;
+/// The widget sends at least 24 slots
+const MIN_SLOTS: usize = 24;
+/// Duration (microseconds) of a break or mark after break time unit
+const TIME_UNIT: f32 = 10.67;
+
+impl EnttecOpt {
+    pub fn validate(&self) -> Result<(), GError> {
+        for &(field, time, min) in [
+            ("break_time", self.break_time, 9),
+            ("mab_time", self.mab_time, 1),
+        ]
+        .iter()
+        {
+            let units = (time / TIME_UNIT).round();
+            if !(units >= min as f32 && units <= 127.0) {
+                return Err(GError::InvalidDmxTiming { field, time });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Append a widget message to `packet`
+fn message(packet: &mut Vec<u8>, label: u8, data: &[u8]) {
+    packet.push(START_OF_MESSAGE);
+    packet.push(label);
+    packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    packet.extend_from_slice(data);
+    packet.push(END_OF_MESSAGE);
+}
+
+/// `Set Widget Parameters` message programming the break timing, the output rate is left
+/// to the widget maximum as frames are paced by the screen
+pub fn enttec_parameters(opt: &EnttecOpt) -> Vec<u8> {
+    let units = |time: f32| (time / TIME_UNIT).round() as u8;
+    let mut packet = vec![];
+    message(
+        &mut packet,
+        ENTTEC_SET_PARAMETERS,
+        &[0, 0, units(opt.break_time), units(opt.mab_time), 0],
+    );
+    packet
+}
+
+/// Serialize univers into `Output Only Send DMX` messages
+#[derive(Debug, Clone, Default)]
+pub struct EnttecSerializer {
+    /// Reused serialization buffer
+    packet: Vec<u8>,
+}
+
+impl EnttecSerializer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Serializer for EnttecSerializer {
+    fn serialize(
+        &mut self,
+        univer: &UniverBuffer,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        let mut data = Vec::with_capacity(univer.slots().len().max(MIN_SLOTS) + 1);
+        data.push(DMX_START_CODE);
+        data.extend_from_slice(univer.slots());
+        data.resize(data.len().max(MIN_SLOTS + 1), 0);
+        self.packet.clear();
+        message(&mut self.packet, ENTTEC_SEND_DMX, &data);
+        send(&self.packet)
+    }
+}
+
+/// Message received by a widget
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnttecMessage {
+    pub label: u8,
+    pub data: Vec<u8>,
+}
+
+impl EnttecMessage {
+    /// Read the next message of a stream
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, GError> {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+        if header[0] != START_OF_MESSAGE {
+            return Err(GError::InvalidPacket("missing Enttec start of message"));
+        }
+        let mut data = vec![0; u16::from_le_bytes([header[2], header[3]]) as usize + 1];
+        reader.read_exact(&mut data)?;
+        if data.pop() != Some(END_OF_MESSAGE) {
+            return Err(GError::InvalidPacket("missing Enttec end of message"));
+        }
+        Ok(Self {
+            label: header[1],
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_dmx_message() {
+        let univer = UniverBuffer::with_slots(0, &(1..=30).collect::<Vec<u8>>());
+        let packet =
+            crate::encoder::serialize_packets(&mut EnttecSerializer::new(), &univer).remove(0);
+        assert_eq!(&packet[0..6], &[0x7E, 6, 31, 0, 0, 1]);
+        assert_eq!(packet.len(), 4 + 31 + 1);
+        assert_eq!(packet[packet.len() - 1], 0xE7);
+        let message = EnttecMessage::read(&mut packet.as_slice()).unwrap();
+        assert_eq!(message.label, ENTTEC_SEND_DMX);
+        assert_eq!(&message.data[1..], univer.slots());
+    }
+
+    #[test]
+    fn short_univers_are_padded() {
+        let univer = UniverBuffer::with_slots(0, &[0xFF; 3]);
+        let packet =
+            crate::encoder::serialize_packets(&mut EnttecSerializer::new(), &univer).remove(0);
+        let message = EnttecMessage::read(&mut packet.as_slice()).unwrap();
+        assert_eq!(message.data.len(), 25);
+        assert_eq!(&message.data[0..5], &[0, 0xFF, 0xFF, 0xFF, 0]);
+    }
+
+    #[test]
+    fn widget_parameters() {
+        let opt = EnttecOpt {
+            break_time: 192.0,
+            mab_time: 21.0,
+            ..EnttecOpt::default()
+        };
+        assert_eq!(
+            enttec_parameters(&opt),
+            vec![0x7E, 4, 5, 0, 0, 0, 18, 2, 0, 0xE7]
+        );
+    }
+
+    #[test]
+    fn timing_validation() {
+        assert!(EnttecOpt::default().validate().is_ok());
+        for (break_time, mab_time) in [(50.0, 10.67), (96.0, 2000.0), (96.0, f32::NAN)].iter() {
+            let opt = EnttecOpt {
+                break_time: *break_time,
+                mab_time: *mab_time,
+                ..EnttecOpt::default()
+            };
+            assert!(opt.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn read_rejects_invalid_messages() {
+        assert!(EnttecMessage::read(&mut &[0x7F, 6, 1, 0, 0, 0xE7][..]).is_err());
+        assert!(EnttecMessage::read(&mut &[0x7E, 6, 1, 0, 0, 0xE8][..]).is_err());
+        assert!(EnttecMessage::read(&mut &[0x7E, 6, 2, 0, 0][..]).is_err());
+    }
+}
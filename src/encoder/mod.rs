@@ -1,9 +1,183 @@
 use super::prelude::*;
+use crate::dmx::{art_dmx_length, ArtDmx, ART_DMX_HEADER_SIZE, ART_DMX_MAX_LENGTH};
+use std::net::SocketAddr;
 
 pub mod artnet;
-use crate::dmx::ArtDmx;
+pub mod ddp;
+pub mod e131;
+pub mod enttec;
+pub mod ola;
+pub mod opc;
 pub use artnet::*;
+pub use ddp::*;
+pub use e131::*;
+pub use enttec::*;
+pub use ola::*;
+pub use opc::*;
+
+/// Protocol neutral content of an univer, serialized by a `Serializer`
+///
+/// The slots are stored in place in an ArtDmx packet, the encoder writes them after the
+/// header so Art-Net sends the univer without copying it
+#[derive(Debug, Clone)]
+pub struct UniverBuffer {
+    /// 15-bit Port-Address of the univer
+    port_address: u16,
+    /// Sequence number of the frame, from 1 to 255 for each Port-Address (0 when the univers
+    /// are not numbered)
+    sequence: u8,
+    /// Number of DMX slots
+    len: usize,
+    /// ArtDmx packet holding the slots, the buffer is longer than the packet for the univers
+    /// of more than 512 slots
+    packet: ArtDmx<Vec<u8>>,
+}
+
+impl UniverBuffer {
+    /// Univer of `len` slots set to 0
+    pub fn new(port_address: u16, len: usize) -> Self {
+        let length = art_dmx_length(len);
+        let buffer = vec![0; ART_DMX_HEADER_SIZE + std::cmp::max(len, length)];
+        let mut packet = ArtDmx::new(buffer, length);
+        packet.set_port_address(port_address);
+        Self {
+            port_address,
+            sequence: 0,
+            len,
+            packet,
+        }
+    }
+
+    /// Univer holding a copy of `slots`
+    pub fn with_slots(port_address: u16, slots: &[u8]) -> Self {
+        let mut univer = Self::new(port_address, slots.len());
+        univer.slots_mut().copy_from_slice(slots);
+        univer
+    }
+
+    pub fn port_address(&self) -> u16 {
+        self.port_address
+    }
+
+    pub fn sequence(&self) -> u8 {
+        self.sequence
+    }
+
+    pub fn set_sequence(&mut self, sequence: u8) {
+        self.sequence = sequence;
+        self.packet.set_sequence(sequence);
+    }
+
+    /// DMX slots (channel levels), the first entry is slot 1
+    pub fn slots(&self) -> &[u8] {
+        &self.packet.payload()[..self.len]
+    }
+
+    pub fn slots_mut(&mut self) -> &mut [u8] {
+        &mut self.packet.payload_mut()[..self.len]
+    }
+
+    /// ArtDmx packet of the univer, an ArtDmx packet holds at most 512 slots
+    pub fn art_dmx(&self) -> Result<&ArtDmx<Vec<u8>>, GError> {
+        if self.len > ART_DMX_MAX_LENGTH {
+            return Err(GError::UniverTooLarge {
+                dmx_size: self.len,
+                max: ART_DMX_MAX_LENGTH,
+            });
+        }
+        Ok(&self.packet)
+    }
+}
+
+impl std::fmt::Display for UniverBuffer {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            fmt,
+            "PORT: {:#06x}, SEQ: {}, LEN: {}",
+            self.port_address, self.sequence, self.len
+        )?;
+        for line in hexdump::hexdump_iter(self.slots()) {
+            writeln!(fmt, "{}", line)?;
+        }
+        Ok(())
+    }
+}
 
 pub trait Encoder {
-    fn encode<'a>(&'a mut self, matrix: &AddrMap, buffer: &[u8]) -> &'a [ArtDmx];
+    /// Encode a 16 bits per channel RGBA buffer into univers
+    fn encode<'a>(&'a mut self, matrix: &AddrMap, buffer: &[u16]) -> &'a [UniverBuffer];
+}
+
+/// Callback sending a packet to a node
+pub type SendTo<'a> = dyn FnMut(&[u8], &SocketAddr) -> Result<(), GError> + 'a;
+
+/// Protocol specific serialization of univers
+pub trait Serializer {
+    /// Called before the univers of a new frame are serialized with the node each univer
+    /// is sent to
+    fn begin_frame(&mut self, _nodes: &[SocketAddr]) {}
+
+    /// Serialize `univer` into one or more packets given to `send`
+    fn serialize(
+        &mut self,
+        univer: &UniverBuffer,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError>;
+
+    /// Called after the univers of a frame are serialized with the nodes they were sent to,
+    /// packets given to `send` are sent to the given node
+    fn end_frame(&mut self, _nodes: &[SocketAddr], _send: &mut SendTo) -> Result<(), GError> {
+        Ok(())
+    }
+
+    /// Serialize the last packets of `univer` when the output stops
+    fn terminate(
+        &mut self,
+        _univer: &UniverBuffer,
+        _send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        Ok(())
+    }
+}
+
+impl<S: Serializer + ?Sized> Serializer for Box<S> {
+    fn begin_frame(&mut self, nodes: &[SocketAddr]) {
+        (**self).begin_frame(nodes)
+    }
+
+    fn serialize(
+        &mut self,
+        univer: &UniverBuffer,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        (**self).serialize(univer, send)
+    }
+
+    fn end_frame(&mut self, nodes: &[SocketAddr], send: &mut SendTo) -> Result<(), GError> {
+        (**self).end_frame(nodes, send)
+    }
+
+    fn terminate(
+        &mut self,
+        univer: &UniverBuffer,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        (**self).terminate(univer, send)
+    }
+}
+
+/// Packets of a single univer
+#[cfg(test)]
+pub(crate) fn serialize_packets<S: Serializer>(
+    serializer: &mut S,
+    univer: &UniverBuffer,
+) -> Vec<Vec<u8>> {
+    let mut packets = vec![];
+    serializer
+        .serialize(univer, &mut |packet| {
+            packets.push(packet.to_vec());
+            Ok(())
+        })
+        .unwrap();
+    packets
 }
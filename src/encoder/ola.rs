@@ -0,0 +1,321 @@
+//!
+//! OLA daemon RPC messages
+//! Based on OLA common/rpc (Rpc.proto) and common/protocol (Ola.proto)
+//!
+//! Each univer is sent as an `UpdateDmxData` request on the OLA univer Port-Address, the
+//! daemon answers every request. Messages are protobuf encoded and prefixed with a 4 bytes
+//! little endian header holding the framing version (4 bits) and the message size (28 bits)
+//!
+
+use super::{Serializer, UniverBuffer};
+use crate::prelude::*;
+use std::io::{Read, Write};
+
+/// olad RPC TCP port
+pub const OLA_PORT: u16 = 9010;
+/// Method sending the DMX data of an univer
+pub const OLA_UPDATE_DMX_DATA: &str = "UpdateDmxData";
+
+const RPC_VERSION: u32 = 1;
+const RPC_SIZE_MASK: u32 = 0x0FFF_FFFF;
+const WIRE_VARINT: u8 = 0;
+const WIRE_LENGTH_DELIMITED: u8 = 2;
+
+/// Type of a RPC message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RpcType {
+    Request = 1,
+    Response = 2,
+    ResponseCancel = 3,
+    ResponseFailed = 4,
+    ResponseNotImplemented = 5,
+    Disconnect = 6,
+    DescriptorNotLoaded = 7,
+    RequestFailed = 8,
+    StreamRequest = 9,
+}
+
+impl RpcType {
+    fn from_u64(value: u64) -> Option<Self> {
+        use RpcType::*;
+        [
+            Request,
+            Response,
+            ResponseCancel,
+            ResponseFailed,
+            ResponseNotImplemented,
+            Disconnect,
+            DescriptorNotLoaded,
+            RequestFailed,
+            StreamRequest,
+        ]
+        .iter()
+        .cloned()
+        .find(|kind| *kind as u64 == value)
+    }
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn write_key(buffer: &mut Vec<u8>, field: u32, wire_type: u8) {
+    write_varint(buffer, u64::from(field << 3 | u32::from(wire_type)));
+}
+
+fn write_bytes(buffer: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_key(buffer, field, WIRE_LENGTH_DELIMITED);
+    write_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn write_uint(buffer: &mut Vec<u8>, field: u32, value: u64) {
+    write_key(buffer, field, WIRE_VARINT);
+    write_varint(buffer, value);
+}
+
+fn read_varint(buffer: &mut &[u8]) -> Result<u64, GError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buffer
+            .split_first()
+            .ok_or(GError::InvalidPacket("truncated protobuf varint"))?;
+        *buffer = rest;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(GError::InvalidPacket("protobuf varint too long"))
+}
+
+/// Protobuf field of a message
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Read the fields of a protobuf message, fields of other wire types are not supported
+fn read_fields(mut buffer: &[u8]) -> Result<Vec<(u32, Field<'_>)>, GError> {
+    let mut fields = vec![];
+    while !buffer.is_empty() {
+        let key = read_varint(&mut buffer)?;
+        let field = match key as u8 & 0x07 {
+            WIRE_VARINT => Field::Varint(read_varint(&mut buffer)?),
+            WIRE_LENGTH_DELIMITED => {
+                let len = read_varint(&mut buffer)? as usize;
+                if buffer.len() < len {
+                    return Err(GError::InvalidPacket("truncated protobuf field"));
+                }
+                let (bytes, rest) = buffer.split_at(len);
+                buffer = rest;
+                Field::Bytes(bytes)
+            }
+            _ => return Err(GError::InvalidPacket("unsupported protobuf wire type")),
+        };
+        fields.push(((key >> 3) as u32, field));
+    }
+    Ok(fields)
+}
+
+/// RPC message exchanged with olad
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcMessage {
+    pub kind: RpcType,
+    /// Identifier of a request, repeated by its response
+    pub id: u32,
+    /// Method of a request
+    pub name: String,
+    /// Protobuf encoded request or response, error message of a failed response
+    pub buffer: Vec<u8>,
+}
+
+impl RpcMessage {
+    /// Append the framed message to `packet`
+    pub fn encode(&self, packet: &mut Vec<u8>) {
+        let start = packet.len();
+        packet.extend_from_slice(&[0; 4]);
+        write_uint(packet, 1, self.kind as u64);
+        write_uint(packet, 2, u64::from(self.id));
+        write_bytes(packet, 3, self.name.as_bytes());
+        write_bytes(packet, 4, &self.buffer);
+        let size = (packet.len() - start - 4) as u32;
+        let header = RPC_VERSION << 28 | (size & RPC_SIZE_MASK);
+        packet[start..start + 4].copy_from_slice(&header.to_le_bytes());
+    }
+
+    /// Decode a message without its framing header
+    pub fn decode(buffer: &[u8]) -> Result<Self, GError> {
+        let mut kind = None;
+        let mut message = Self {
+            kind: RpcType::Request,
+            id: 0,
+            name: String::new(),
+            buffer: vec![],
+        };
+        for (number, field) in read_fields(buffer)? {
+            match (number, field) {
+                (1, Field::Varint(value)) => kind = RpcType::from_u64(value),
+                (2, Field::Varint(value)) => message.id = value as u32,
+                (3, Field::Bytes(bytes)) => {
+                    message.name = String::from_utf8_lossy(bytes).into_owned()
+                }
+                (4, Field::Bytes(bytes)) => message.buffer = bytes.to_vec(),
+                _ => {}
+            }
+        }
+        message.kind = kind.ok_or(GError::InvalidPacket("missing OLA RPC message type"))?;
+        Ok(message)
+    }
+
+    /// Read the next framed message of a stream
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, GError> {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+        let header = u32::from_le_bytes(header);
+        if header >> 28 != RPC_VERSION {
+            return Err(GError::InvalidPacket("unsupported OLA RPC version"));
+        }
+        let mut buffer = vec![0; (header & RPC_SIZE_MASK) as usize];
+        reader.read_exact(&mut buffer)?;
+        Self::decode(&buffer)
+    }
+
+    /// Write the framed message to a stream
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), GError> {
+        let mut packet = vec![];
+        self.encode(&mut packet);
+        writer.write_all(&packet)?;
+        Ok(())
+    }
+}
+
+/// Request of the `UpdateDmxData` method
+#[derive(Debug, Clone, PartialEq)]
+pub struct DmxData {
+    pub universe: u32,
+    pub data: Vec<u8>,
+    pub priority: Option<u8>,
+}
+
+impl DmxData {
+    pub fn encode(&self, buffer: &mut Vec<u8>) {
+        write_uint(buffer, 1, u64::from(self.universe));
+        write_bytes(buffer, 2, &self.data);
+        if let Some(priority) = self.priority {
+            write_uint(buffer, 3, u64::from(priority));
+        }
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, GError> {
+        let mut dmx = Self {
+            universe: 0,
+            data: vec![],
+            priority: None,
+        };
+        for (number, field) in read_fields(buffer)? {
+            match (number, field) {
+                (1, Field::Varint(value)) => dmx.universe = value as u32,
+                (2, Field::Bytes(bytes)) => dmx.data = bytes.to_vec(),
+                (3, Field::Varint(value)) => dmx.priority = Some(value as u8),
+                _ => {}
+            }
+        }
+        Ok(dmx)
+    }
+}
+
+/// Serialize univers into `UpdateDmxData` requests
+#[derive(Debug, Clone, Default)]
+pub struct OlaSerializer {
+    /// Identifier of the next request
+    id: u32,
+    /// Reused serialization buffer
+    packet: Vec<u8>,
+}
+
+impl OlaSerializer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Serializer for OlaSerializer {
+    fn serialize(
+        &mut self,
+        univer: &UniverBuffer,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        let mut request = vec![];
+        DmxData {
+            universe: u32::from(univer.port_address()),
+            data: univer.slots().to_vec(),
+            priority: None,
+        }
+        .encode(&mut request);
+        let message = RpcMessage {
+            kind: RpcType::Request,
+            id: self.id,
+            name: String::from(OLA_UPDATE_DMX_DATA),
+            buffer: request,
+        };
+        self.id = self.id.wrapping_add(1);
+        self.packet.clear();
+        message.encode(&mut self.packet);
+        send(&self.packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint() {
+        let mut buffer = vec![];
+        write_varint(&mut buffer, 300);
+        assert_eq!(buffer, vec![0xAC, 0x02]);
+        assert_eq!(read_varint(&mut buffer.as_slice()).unwrap(), 300);
+        assert!(read_varint(&mut &[0x80][..]).is_err());
+    }
+
+    #[test]
+    fn update_dmx_data_request() {
+        let univer = UniverBuffer::with_slots(3, &[1, 2, 3]);
+        let mut serializer = OlaSerializer::new();
+        let packets = crate::encoder::serialize_packets(&mut serializer, &univer);
+        #[rustfmt::skip]
+        let expected = vec![
+            28, 0, 0, 0x10,
+            0x08, 1,
+            0x10, 0,
+            0x1A, 13, b'U', b'p', b'd', b'a', b't', b'e', b'D', b'm', b'x', b'D', b'a', b't', b'a',
+            0x22, 7, 0x08, 3, 0x12, 3, 1, 2, 3,
+        ];
+        assert_eq!(packets, vec![expected]);
+        let second = crate::encoder::serialize_packets(&mut serializer, &univer).remove(0);
+        let message = RpcMessage::read(&mut second.as_slice()).unwrap();
+        assert_eq!(message.id, 1);
+        assert_eq!(
+            DmxData::decode(&message.buffer).unwrap(),
+            DmxData {
+                universe: 3,
+                data: vec![1, 2, 3],
+                priority: None,
+            }
+        );
+    }
+
+    #[test]
+    fn read_rejects_invalid_messages() {
+        assert!(RpcMessage::read(&mut &[0, 0, 0, 0x20][..]).is_err());
+        assert!(RpcMessage::read(&mut &[2, 0, 0, 0x10, 0x10, 1][..]).is_err());
+        assert!(RpcMessage::read(&mut &[2, 0, 0, 0x10, 0x1A, 4][..]).is_err());
+        let message = RpcMessage::read(&mut &[2, 0, 0, 0x10, 0x08, 2][..]).unwrap();
+        assert_eq!(message.kind, RpcType::Response);
+    }
+}
@@ -0,0 +1,181 @@
+//!
+//! Open Pixel Control messages
+//! Based on http://openpixelcontrol.org/
+//!
+//! Each univer is sent as a `Set Pixel Colors` message on the OPC channel Port-Address + 1
+//! (channel 0 is the broadcast channel)
+//!
+
+use super::{Serializer, UniverBuffer};
+use crate::prelude::*;
+use std::io::Read;
+
+/// Default OPC server TCP port
+pub const OPC_PORT: u16 = 7890;
+/// Maximum data length of a message
+pub const OPC_MAX_DATA: usize = 0xFFFF;
+/// Command of the `Set Pixel Colors` message (8 bits RGB pixels)
+pub const OPC_SET_PIXEL_COLORS: u8 = 0;
+
+const HEADER_SIZE: usize = 4;
+
+/// Serialize univers into `Set Pixel Colors` messages
+#[derive(Debug, Clone, Default)]
+pub struct OpcSerializer {
+    /// Reused serialization buffer
+    packet: Vec<u8>,
+}
+
+impl OpcSerializer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Serializer for OpcSerializer {
+    fn serialize(
+        &mut self,
+        univer: &UniverBuffer,
+        send: &mut dyn FnMut(&[u8]) -> Result<(), GError>,
+    ) -> Result<(), GError> {
+        let channel = univer.port_address() + 1;
+        if channel > 0xFF {
+            return Err(GError::InvalidPortAddress {
+                address: univer.port_address() as usize,
+            });
+        }
+        if univer.slots().len() > OPC_MAX_DATA {
+            return Err(GError::UniverTooLarge {
+                dmx_size: univer.slots().len(),
+                max: OPC_MAX_DATA,
+            });
+        }
+        self.packet.clear();
+        self.packet.push(channel as u8);
+        self.packet.push(OPC_SET_PIXEL_COLORS);
+        self.packet
+            .extend_from_slice(&(univer.slots().len() as u16).to_be_bytes());
+        self.packet.extend_from_slice(univer.slots());
+        send(&self.packet)
+    }
+}
+
+/// Message received by an OPC server
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpcMessage {
+    pub channel: u8,
+    pub command: u8,
+    pub data: Vec<u8>,
+}
+
+impl OpcMessage {
+    /// Read the next message of a stream
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, GError> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        let mut data = vec![0; u16::from_be_bytes([header[2], header[3]]) as usize];
+        reader.read_exact(&mut data)?;
+        Ok(Self {
+            channel: header[0],
+            command: header[1],
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::net::{SocketAddr, TcpListener};
+
+    fn mapping(color_mode: ColorMode, nodes: Option<Vec<SocketAddr>>) -> MappingOpt {
+        MappingOpt {
+            dmx_size: 12,
+            width: 4,
+            height: 2,
+            univer_height: 2,
+            color_mode,
+            displacement: Displacement::Progressive,
+            direction: Direction::Vertical,
+            orientation: vec![Orientation::TopLeft; 4],
+            start_address: 0,
+            port_addresses: None,
+            channels: None,
+            color_depth: ColorDepth::Bit8,
+            correction: CorrectionOpt::default(),
+            power: PowerOpt::default(),
+            dithering: false,
+            pacing: PacingOpt::default(),
+            nodes,
+            protocol: Protocol::Opc,
+            artnet: ArtnetOpt::default(),
+        }
+    }
+
+    fn opt(color_mode: ColorMode, nodes: Option<Vec<SocketAddr>>) -> MappingOptExt {
+        MappingOptExt::try_from(mapping(color_mode, nodes)).unwrap()
+    }
+
+    #[test]
+    fn only_rgb_pixels() {
+        assert!(MappingOptExt::try_from(mapping(ColorMode::RGB, None)).is_ok());
+        assert!(MappingOptExt::try_from(mapping(ColorMode::RGBW, None)).is_err());
+        let mut bit16 = mapping(ColorMode::RGB, None);
+        bit16.color_depth = ColorDepth::Bit16;
+        bit16.dmx_size = 24;
+        assert!(MappingOptExt::try_from(bit16).is_err());
+    }
+
+    #[test]
+    fn oversized_univer_is_rejected() {
+        let univer = UniverBuffer::with_slots(0, &[0; OPC_MAX_DATA + 1]);
+        let mut send = |_: &[u8]| Ok(());
+        match OpcSerializer::new().serialize(&univer, &mut send) {
+            Err(GError::UniverTooLarge { dmx_size, max }) => {
+                assert_eq!((dmx_size, max), (OPC_MAX_DATA + 1, OPC_MAX_DATA))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn default_node_is_local_server() {
+        let opt = opt(ColorMode::RGB, None);
+        assert_eq!(
+            opt.nodes,
+            vec![SocketAddr::from(([127, 0, 0, 1], OPC_PORT)); 2]
+        );
+    }
+
+    #[test]
+    fn set_pixel_colors_message() {
+        let univer = UniverBuffer::with_slots(2, &[1, 2, 3, 4, 5, 6]);
+        let mut serializer = OpcSerializer::new();
+        assert_eq!(
+            crate::encoder::serialize_packets(&mut serializer, &univer),
+            vec![vec![3, 0, 0, 6, 1, 2, 3, 4, 5, 6]]
+        );
+    }
+
+    #[test]
+    fn send_to_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let node = listener.local_addr().unwrap();
+        let opt = opt(ColorMode::RGB, Some(vec![node; 2]));
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        let serializer = OpcSerializer::new();
+        let mut router = Router::from_mapping(&opt, serializer, TcpTransport::new());
+        let buffer: Vec<u16> = (0..8 * 4).map(|i| upscale(i as u8)).collect();
+        router.send(encoder.encode(&matrix, &buffer)).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let first = OpcMessage::read(&mut stream).unwrap();
+        let second = OpcMessage::read(&mut stream).unwrap();
+        assert_eq!((first.channel, first.command), (1, OPC_SET_PIXEL_COLORS));
+        assert_eq!(second.channel, 2);
+        // Pixel (0, 0) then pixel (0, 1)
+        assert_eq!(&first.data[0..6], &[0, 1, 2, 16, 17, 18]);
+        assert_eq!(second.data.len(), 12);
+    }
+}
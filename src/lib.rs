@@ -8,13 +8,22 @@ extern crate static_assertions;
 extern crate serde;
 extern crate fps_counter;
 extern crate termion;
+pub mod correction;
+pub mod dithering;
 pub mod dmx;
 pub mod encoder;
 pub mod matrix;
+pub mod node;
 pub mod options;
+pub mod pacing;
+pub mod power;
 pub mod prelude;
 pub mod screen;
+pub mod sender;
+pub mod transform;
+pub mod transport;
 use prelude::*;
+use std::convert::TryFrom;
 ///
 /// Library error (returned by all public method)
 ///
@@ -22,6 +31,111 @@ use prelude::*;
 pub enum GError {
     #[fail(display = "Invalide matrix configuration: {}", 0)]
     WrongConfig(&'static str),
+    #[fail(display = "Invalide matrix configuration: `{}` can't be zero", field)]
+    ZeroSize { field: &'static str },
+    #[fail(
+        display = "Invalide matrix configuration: dmx_size {} exceed the {} channels of an univer",
+        dmx_size, max
+    )]
+    UniverTooLarge { dmx_size: usize, max: usize },
+    #[fail(
+        display = "Invalide matrix configuration: univer_height {} doesn't fit into an univer of {} pixels",
+        univer_height, pixel_per_univer
+    )]
+    UniverHeightTooLarge {
+        univer_height: usize,
+        pixel_per_univer: usize,
+    },
+    #[fail(
+        display = "Invalide matrix configuration: height {} is not a multiple of univer_height {}",
+        height, univer_height
+    )]
+    UnalignedHeight { height: usize, univer_height: usize },
+    #[fail(
+        display = "Invalide matrix configuration: {} orientation provided but {} univers are needed",
+        found, expected
+    )]
+    MissingOrientation { expected: usize, found: usize },
+    #[fail(
+        display = "Invalide matrix configuration: {} port address provided but {} univers are needed",
+        found, expected
+    )]
+    MissingPortAddress { expected: usize, found: usize },
+    #[fail(
+        display = "Invalide matrix configuration: port address {} exceed the 15 bits Art-Net Port-Address",
+        address
+    )]
+    InvalidPortAddress { address: usize },
+    #[fail(
+        display = "Invalide matrix configuration: {} node address provided but {} univers are needed",
+        found, expected
+    )]
+    MissingNode { expected: usize, found: usize },
+    #[fail(
+        display = "Invalide matrix configuration: node {} doesn't report enough output ports for {} univers",
+        node, missing
+    )]
+    MissingNodePort {
+        node: std::net::SocketAddr,
+        missing: usize,
+    },
+    #[fail(
+        display = "Invalide matrix configuration: gamma {} must be a positive number",
+        gamma
+    )]
+    InvalidGamma { gamma: f32 },
+    #[fail(
+        display = "Invalide matrix configuration: white balance {} must be between 0.0 and 1.0",
+        value
+    )]
+    InvalidWhiteBalance { value: f32 },
+    #[fail(
+        display = "Invalide matrix configuration: brightness {} must be between 0.0 and 1.0",
+        brightness
+    )]
+    InvalidBrightness { brightness: f32 },
+    #[fail(
+        display = "Invalide matrix configuration: current {} mA must be a positive number",
+        current
+    )]
+    InvalidCurrent { current: f32 },
+    #[fail(
+        display = "Invalide matrix configuration: refresh rate {} Hz must be a positive number",
+        rate
+    )]
+    InvalidRefreshRate { rate: f32 },
+    #[fail(
+        display = "Invalide sACN configuration: priority {} exceed 200",
+        priority
+    )]
+    InvalidPriority { priority: u8 },
+    #[fail(
+        display = "Invalide Enttec configuration: {} {} us is out of the widget range",
+        field, time
+    )]
+    InvalidDmxTiming { field: &'static str, time: f32 },
+    #[fail(display = "Invalide packet: {}", _0)]
+    InvalidPacket(&'static str),
+    #[fail(display = "OLA request failed: {}", _0)]
+    OlaRequest(String),
+    #[fail(display = "Output thread is not running")]
+    OutputStopped,
+    #[fail(display = "I/O error: {}", _0)]
+    Io(#[cause] std::io::Error),
+    #[fail(display = "Art-Net protocol error: {}", _0)]
+    Artnet(#[cause] artnet_protocol::Error),
+}
+
+impl From<std::io::Error> for GError {
+    fn from(error: std::io::Error) -> Self {
+        GError::Io(error)
+    }
+}
+
+impl From<artnet_protocol::Error> for GError {
+    fn from(error: artnet_protocol::Error) -> Self {
+        GError::Artnet(error)
+    }
 }
 
 impl GError {
@@ -33,9 +147,33 @@ impl GError {
     }
 }
 
-pub fn init_arnet_screen(opt: MappingOpt) -> Screen<ArtnetEncoder> {
-    let opt: MappingOptExt = opt.into();
+/// Serializer of the protocol given by the matrix configuration
+pub fn init_serializer(opt: &MappingOptExt) -> Result<Box<dyn Serializer + Send>, GError> {
+    Ok(match &opt.protocol {
+        Protocol::Artnet => Box::new(ArtnetSerializer::new(&opt.artnet)),
+        Protocol::E131(e131) => Box::new(E131Serializer::new(e131)?),
+        Protocol::Ddp => Box::new(DdpSerializer::new(opt)),
+        Protocol::Opc => Box::new(OpcSerializer::new()),
+        Protocol::Enttec(_) => Box::new(EnttecSerializer::new()),
+        Protocol::Ola => Box::new(OlaSerializer::new()),
+    })
+}
+
+/// Transport of the protocol given by the matrix configuration
+pub fn init_transport(opt: &MappingOptExt) -> Result<Box<dyn Transport + Send>, GError> {
+    Ok(match &opt.protocol {
+        // Art-Net nodes reply to the Art-Net port
+        Protocol::Artnet => Box::new(UdpTransport::bind(("0.0.0.0", ARTNET_PORT))?),
+        Protocol::E131(_) | Protocol::Ddp => Box::new(UdpTransport::bind(("0.0.0.0", 0))?),
+        Protocol::Opc => Box::new(TcpTransport::new()),
+        Protocol::Enttec(enttec) => Box::new(SerialTransport::open(enttec)?),
+        Protocol::Ola => Box::new(OlaTransport::new()),
+    })
+}
+
+pub fn init_arnet_screen(opt: MappingOpt) -> Result<Screen<ArtnetEncoder>, GError> {
+    let opt = MappingOptExt::try_from(opt)?;
     let map = AddrMap::from_mapping(opt.clone());
     let encoder = ArtnetEncoder::new(opt.clone());
-    Screen::new(map, encoder)
+    Ok(Screen::new(map, encoder))
 }
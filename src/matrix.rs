@@ -1,6 +1,11 @@
+use crate::encoder::{e131_multicast_addr, ARTSYNC_TIMEOUT, OLA_PORT, OPC_MAX_DATA, OPC_PORT};
 use crate::options::*;
+use crate::transport::ARTNET_PORT;
+use crate::GError;
 use std::cmp::{max, min};
+use std::convert::TryFrom;
 use std::fmt;
+use std::net::SocketAddr;
 use termion::color;
 
 /// Made from a `Mapping` (two dimensional matrix of [x,y] -> dxm address/univer id)
@@ -14,6 +19,15 @@ pub struct AddrMap {
 /// A "Mapping" is two dieemensional matrix that contains pixel address for a given X,Y
 type Mapping = Vec<Vec<PixelAddr>>;
 
+/// Maximum number of channels of a DMX512 univer
+pub const DMX_UNIVER_SIZE: usize = 512;
+
+/// Number of values of an input pixel (RGBA)
+pub const INPUT_PIXEL_SIZE: usize = 4;
+
+/// Highest Art-Net Port-Address (15 bits)
+pub const MAX_PORT_ADDRESS: u16 = 0x7FFF;
+
 /// Used as internal configuration this struct should be generated from
 #[derive(Debug, Clone)]
 pub struct MappingOptExt {
@@ -27,7 +41,24 @@ pub struct MappingOptExt {
     pub displacement: Displacement,
     pub direction: Direction,
     pub orientation: Vec<Orientation>,
+    /// Number of DMX slots used by one pixel on the fixture side
     pub pixel_size: usize,
+    /// Number of values used by one pixel in the input buffer
+    pub input_pixel_size: usize,
+    /// Channels sent to the fixture for each pixel
+    pub channels: Vec<Channel>,
+    /// Number of bits of each fixture channel
+    pub color_depth: ColorDepth,
+    pub correction: CorrectionOpt,
+    pub power: PowerOpt,
+    pub dithering: bool,
+    pub pacing: PacingOpt,
+    /// Art-Net Port-Address of each univer
+    pub port_addresses: Vec<u16>,
+    /// Node address of each univer
+    pub nodes: Vec<SocketAddr>,
+    pub protocol: Protocol,
+    pub artnet: ArtnetOpt,
 }
 
 /// the PreMapping is a tree dimensional matrix of [univer, x in univer, y in univer]
@@ -85,20 +116,15 @@ impl fmt::Display for AddrMap {
 
 impl PreMapping {
     fn new(opt: MappingOptExt) -> Self {
-        let chunk: Vec<Vec<PixelAddr>> = (0..opt.univer_width)
-            .map(|x| {
-                (0..opt.univer_height)
-                    .map(|y| PixelAddr {
-                        address: (y + (opt.univer_height * x)) * opt.pixel_size,
-                        univer: 0,
-                    })
-                    .collect()
-            })
-            .collect();
-        // You can match more displasement type here
         let mapping = (0..opt.univer_per_row * opt.univer_per_column)
             .map(|univer| {
-                let chunk = Self::displacement_zig_zag(chunk.clone());
+                // The univers of the last column and row are cut by the matrix edges, their
+                // lines are wired on the pixels that exist
+                let x_offset = (univer % opt.univer_per_column) * opt.univer_width;
+                let y_offset = (univer / opt.univer_per_column) * opt.univer_height;
+                let width = min(opt.univer_width, opt.width - x_offset);
+                let height = min(opt.univer_height, opt.height - y_offset);
+                let chunk = Self::displacement(&opt, width, height);
                 let mut chunk = Self::orientation(chunk, opt.orientation[univer]);
                 chunk
                     .iter_mut()
@@ -110,6 +136,43 @@ impl PreMapping {
         Self(mapping, opt)
     }
 
+    /// Build a `width` x `height` univer chunk where addresses follow the wiring described by
+    /// `opt.displacement` along the lines given by `opt.direction`
+    fn displacement(opt: &MappingOptExt, width: usize, height: usize) -> Vec<Vec<PixelAddr>> {
+        // Lines are columns when wired vertically and rows when wired horizontally
+        let (line_count, line_len) = match opt.direction {
+            Direction::Vertical => (width, height),
+            Direction::Horizontal => (height, width),
+        };
+        let lines: Vec<Vec<PixelAddr>> = (0..line_count)
+            .map(|line| {
+                (0..line_len)
+                    .map(|pos| PixelAddr {
+                        address: (pos + (line_len * line)) * opt.pixel_size,
+                        univer: 0,
+                    })
+                    .collect()
+            })
+            .collect();
+        // You can match more displasement type here
+        let lines = match opt.displacement {
+            Displacement::Progressive => lines,
+            Displacement::Snake => Self::displacement_snake(lines),
+            Displacement::ZigZag => Self::displacement_zig_zag(lines),
+        };
+        match opt.direction {
+            Direction::Vertical => lines,
+            Direction::Horizontal => Self::transpose(lines, line_len),
+        }
+    }
+
+    /// Swap the two axis of a chunk ([line][pos] -> [pos][line])
+    fn transpose(chunk: Vec<Vec<PixelAddr>>, line_len: usize) -> Vec<Vec<PixelAddr>> {
+        (0..line_len)
+            .map(|pos| chunk.iter().map(|line| line[pos]).collect())
+            .collect()
+    }
+
     fn orientation(
         mut chunk: Vec<Vec<PixelAddr>>,
         orientation: Orientation,
@@ -140,18 +203,45 @@ impl PreMapping {
         }
     }
 
-    fn displacement_zig_zag(chunk: Vec<Vec<PixelAddr>>) -> Vec<Vec<PixelAddr>> {
+    fn displacement_snake(chunk: Vec<Vec<PixelAddr>>) -> Vec<Vec<PixelAddr>> {
         chunk
             .into_iter()
             .enumerate()
-            .map(|(x, mut line)| {
-                if x % 2 != 0 {
+            .map(|(idx, mut line)| {
+                if idx % 2 != 0 {
                     line.reverse();
                 }
                 line
             })
             .collect()
     }
+
+    /// Walk the chunk along its anti-diagonals, alternating the direction on each of them
+    /// (first step goes along the line, the second one goes back to the first line, ...).
+    /// This is the wiring of a serpentine strip laid along the diagonals of the univer, as
+    /// found on panels mounted rotated by 45 degrees (diamond layout)
+    fn displacement_zig_zag(chunk: Vec<Vec<PixelAddr>>) -> Vec<Vec<PixelAddr>> {
+        let line_count = chunk.len();
+        let line_len = chunk.first().map_or(0, Vec::len);
+        // Addresses of a progressive chunk are already sorted when flattened
+        let addresses: Vec<usize> = chunk.iter().flatten().map(|pixel| pixel.address).collect();
+        let mut addresses = addresses.into_iter();
+        let mut result = chunk.clone();
+        for diagonal in 0..(line_count + line_len).saturating_sub(1) {
+            let mut lines: Vec<usize> = (0..=diagonal)
+                .filter(|line| *line < line_count && diagonal - line < line_len)
+                .collect();
+            if diagonal % 2 == 0 {
+                lines.reverse();
+            }
+            for line in lines {
+                result[line][diagonal - line].address = addresses
+                    .next()
+                    .expect("the diagonals cover every pixel of the chunk");
+            }
+        }
+        result
+    }
 }
 
 impl Into<Mapping> for PreMapping {
@@ -176,25 +266,157 @@ impl Into<Mapping> for PreMapping {
     }
 }
 
-impl From<MappingOpt> for MappingOptExt {
-    fn from(opt: MappingOpt) -> Self {
-        if opt.dmx_size < opt.univer_height {
-            panic!("Univer height must be lesser or equal to dmx buffer size");
+impl TryFrom<MappingOpt> for MappingOptExt {
+    type Error = GError;
+
+    fn try_from(opt: MappingOpt) -> Result<Self, GError> {
+        for &(field, value) in [
+            ("dmx_size", opt.dmx_size),
+            ("width", opt.width),
+            ("height", opt.height),
+            ("univer_height", opt.univer_height),
+        ]
+        .iter()
+        {
+            if value == 0 {
+                return Err(GError::ZeroSize { field });
+            }
+        }
+        // DDP and OPC univers are not DMX512 univers
+        let max_size = match opt.protocol {
+            Protocol::Ddp => usize::MAX,
+            Protocol::Opc => OPC_MAX_DATA,
+            _ => DMX_UNIVER_SIZE,
+        };
+        if opt.dmx_size > max_size {
+            return Err(GError::UniverTooLarge {
+                dmx_size: opt.dmx_size,
+                max: max_size,
+            });
+        }
+        let channels = match opt.channels {
+            Some(channels) => channels,
+            None => opt.color_mode.channels().to_vec(),
+        };
+        if channels.is_empty() {
+            return Err(GError::ZeroSize { field: "channels" });
+        }
+        let nbr_led_per_pixel = channels.len() * opt.color_depth.slots();
+        if opt.dmx_size / nbr_led_per_pixel < opt.univer_height {
+            return Err(GError::UniverHeightTooLarge {
+                univer_height: opt.univer_height,
+                pixel_per_univer: opt.dmx_size / nbr_led_per_pixel,
+            });
+        }
+        opt.correction.validate()?;
+        opt.power.validate()?;
+        opt.pacing.validate()?;
+        // Nodes return to non-synchronous mode after 4 s without ArtSync
+        if let (Protocol::Artnet, true) = (
+            &opt.protocol,
+            opt.artnet.sync && opt.pacing.keep_alive >= ARTSYNC_TIMEOUT,
+        ) {
+            return Err(GError::WrongConfig(
+                "Art-Net sync requires a keep_alive shorter than 4000 ms",
+            ));
+        }
+        match &opt.protocol {
+            Protocol::E131(e131) => e131.validate()?,
+            Protocol::Enttec(enttec) => enttec.validate()?,
+            // OPC pixels are 8 bits RGB
+            Protocol::Opc
+                if channels != [Channel::Red, Channel::Green, Channel::Blue]
+                    || opt.color_depth != ColorDepth::Bit8 =>
+            {
+                return Err(GError::WrongConfig("OPC only supports 8 bits RGB pixels"));
+            }
+            _ => {}
         }
-        let nbr_led_per_pixel = opt.color_mode as usize;
         let nbr_led_per_channel = opt.dmx_size / nbr_led_per_pixel;
-        let nbr_led_per_channel = nbr_led_per_channel - (nbr_led_per_channel % opt.univer_height);
-        // How many width can be handled per univer
+        // An univer holds `univer_height` rows of `univer_width` pixels
         let univer_width = min(nbr_led_per_channel / opt.univer_height, opt.width);
-        // How many univers are superposed
-        let univer_per_row =
-            (opt.height / opt.univer_height) + (opt.height % opt.univer_height != 0) as usize;
-        let univer_per_column =
-            (opt.width / univer_width) + (opt.width % univer_width != 0) as usize;
-        if opt.height % opt.univer_height != 0 {
-            println!("Warning: univer height is not well steup, last univer height can't equal other univer height this can result of unedifned behaviour");
+        // The univers cut by the matrix edges only wire the pixels that exist
+        let (univer_per_row, univer_per_column) = match opt.direction {
+            // Columns of `univer_height` pixels can't be cut, the last univer of a row holds
+            // fewer columns
+            Direction::Vertical => {
+                if opt.height % opt.univer_height != 0 {
+                    return Err(GError::UnalignedHeight {
+                        height: opt.height,
+                        univer_height: opt.univer_height,
+                    });
+                }
+                (
+                    opt.height / opt.univer_height,
+                    opt.width.div_ceil(univer_width),
+                )
+            }
+            // Rows of `univer_width` pixels, the last univer of a column holds fewer rows and
+            // the last univer of a row holds shorter rows
+            Direction::Horizontal => (
+                opt.height.div_ceil(opt.univer_height),
+                opt.width.div_ceil(univer_width),
+            ),
+        };
+        if opt.orientation.len() < univer_per_row * univer_per_column {
+            return Err(GError::MissingOrientation {
+                expected: univer_per_row * univer_per_column,
+                found: opt.orientation.len(),
+            });
         }
-        Self {
+        let univer_count = univer_per_row * univer_per_column;
+        if let (Protocol::Enttec(_), true) = (&opt.protocol, univer_count > 1) {
+            return Err(GError::WrongConfig(
+                "an Enttec DMX USB Pro outputs a single univer",
+            ));
+        }
+        let start_address = opt.start_address as usize;
+        let port_addresses: Vec<usize> = match opt.port_addresses {
+            Some(addresses) if addresses.len() < univer_count => {
+                return Err(GError::MissingPortAddress {
+                    expected: univer_count,
+                    found: addresses.len(),
+                })
+            }
+            Some(addresses) => addresses.into_iter().map(usize::from).collect(),
+            None => (0..univer_count)
+                .map(|univer| start_address + univer)
+                .collect(),
+        };
+        if let Some(&address) = port_addresses
+            .iter()
+            .find(|address| **address > MAX_PORT_ADDRESS as usize)
+        {
+            return Err(GError::InvalidPortAddress { address });
+        }
+        let broadcast = SocketAddr::from(([255, 255, 255, 255], ARTNET_PORT));
+        let nodes = match opt.nodes {
+            Some(nodes) if nodes.len() < univer_count => {
+                return Err(GError::MissingNode {
+                    expected: univer_count,
+                    found: nodes.len(),
+                })
+            }
+            Some(nodes) => nodes,
+            None => match opt.protocol {
+                Protocol::Artnet => vec![broadcast; univer_count],
+                Protocol::E131(_) => port_addresses
+                    .iter()
+                    .map(|address| e131_multicast_addr(*address as u16 + 1))
+                    .collect(),
+                Protocol::Opc => vec![SocketAddr::from(([127, 0, 0, 1], OPC_PORT)); univer_count],
+                Protocol::Ola => vec![SocketAddr::from(([127, 0, 0, 1], OLA_PORT)); univer_count],
+                // The widget is not a network node
+                Protocol::Enttec(_) => vec![SocketAddr::from(([0, 0, 0, 0], 0)); univer_count],
+                Protocol::Ddp => {
+                    return Err(GError::MissingNode {
+                        expected: univer_count,
+                        found: 0,
+                    })
+                }
+            },
+        };
+        Ok(Self {
             width: opt.width,
             height: opt.height,
             univer_width,
@@ -206,7 +428,18 @@ impl From<MappingOpt> for MappingOptExt {
             direction: opt.direction,
             orientation: opt.orientation,
             pixel_size: nbr_led_per_pixel,
-        }
+            input_pixel_size: INPUT_PIXEL_SIZE,
+            channels,
+            color_depth: opt.color_depth,
+            correction: opt.correction,
+            power: opt.power,
+            dithering: opt.dithering,
+            pacing: opt.pacing,
+            port_addresses: port_addresses.into_iter().map(|a| a as u16).collect(),
+            nodes,
+            protocol: opt.protocol,
+            artnet: opt.artnet,
+        })
     }
 }
 
@@ -246,4 +479,529 @@ impl From<AddrMap> for RevAddrMap {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::encoder::DDP_PORT;
+    use std::convert::TryInto;
+
+    /// A single 3x2 univer of RGB pixels
+    fn mapping(
+        displacement: Displacement,
+        direction: Direction,
+        orientation: Orientation,
+    ) -> MappingOpt {
+        MappingOpt {
+            dmx_size: 18,
+            width: 3,
+            height: 2,
+            univer_height: 2,
+            color_mode: ColorMode::RGB,
+            displacement,
+            direction,
+            orientation: vec![orientation],
+            start_address: 0,
+            port_addresses: None,
+            channels: None,
+            color_depth: ColorDepth::Bit8,
+            correction: CorrectionOpt::default(),
+            power: PowerOpt::default(),
+            dithering: false,
+            pacing: PacingOpt::default(),
+            nodes: None,
+            protocol: Protocol::Artnet,
+            artnet: ArtnetOpt::default(),
+        }
+    }
+
+    fn opt(
+        displacement: Displacement,
+        direction: Direction,
+        orientation: Orientation,
+    ) -> MappingOptExt {
+        mapping(displacement, direction, orientation)
+            .try_into()
+            .unwrap()
+    }
+
+    /// (univer, pixel index) of every pixel as [y][x]
+    fn rows(map: &AddrMap) -> Vec<Vec<(usize, usize)>> {
+        (0..map.opt.height)
+            .map(|y| {
+                (0..map.opt.width)
+                    .map(|x| {
+                        (
+                            map.addr[x][y].univer,
+                            map.addr[x][y].address / map.opt.pixel_size,
+                        )
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Pixel index (address / pixel_size) of every pixel as [x][y]
+    fn layout(map: &AddrMap) -> Vec<Vec<usize>> {
+        map.addr
+            .iter()
+            .map(|column| {
+                column
+                    .iter()
+                    .map(|pixel| pixel.address / map.opt.pixel_size)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn displacement_progressive() {
+        let map = AddrMap::from_mapping(opt(
+            Displacement::Progressive,
+            Direction::Vertical,
+            Orientation::TopLeft,
+        ));
+        assert_eq!(layout(&map), vec![vec![0, 1], vec![2, 3], vec![4, 5]]);
+    }
+
+    #[test]
+    fn displacement_snake() {
+        let map = AddrMap::from_mapping(opt(
+            Displacement::Snake,
+            Direction::Vertical,
+            Orientation::TopLeft,
+        ));
+        assert_eq!(layout(&map), vec![vec![0, 1], vec![3, 2], vec![4, 5]]);
+    }
+
+    #[test]
+    fn displacement_zig_zag() {
+        let map = AddrMap::from_mapping(opt(
+            Displacement::ZigZag,
+            Direction::Vertical,
+            Orientation::TopLeft,
+        ));
+        assert_eq!(layout(&map), vec![vec![0, 1], vec![2, 4], vec![3, 5]]);
+    }
+
+    #[test]
+    fn displacement_keeps_univer_and_pixel_size() {
+        let map = AddrMap::from_mapping(opt(
+            Displacement::ZigZag,
+            Direction::Vertical,
+            Orientation::TopLeft,
+        ));
+        for pixel in map.addr.iter().flatten() {
+            assert_eq!(pixel.univer, 0);
+            assert_eq!(pixel.address % 3, 0);
+        }
+    }
+
+    #[test]
+    fn direction_vertical_with_orientation() {
+        let expected = [
+            (
+                Orientation::TopLeft,
+                vec![vec![0, 1], vec![3, 2], vec![4, 5]],
+            ),
+            (
+                Orientation::TopRight,
+                vec![vec![4, 5], vec![3, 2], vec![0, 1]],
+            ),
+            (
+                Orientation::BottomLeft,
+                vec![vec![1, 0], vec![2, 3], vec![5, 4]],
+            ),
+            (
+                Orientation::BottomRight,
+                vec![vec![5, 4], vec![2, 3], vec![1, 0]],
+            ),
+        ];
+        for (orientation, layout_expected) in expected.iter() {
+            let map =
+                AddrMap::from_mapping(opt(Displacement::Snake, Direction::Vertical, *orientation));
+            assert_eq!(&layout(&map), layout_expected, "{:?}", orientation);
+        }
+    }
+
+    #[test]
+    fn direction_horizontal_with_orientation() {
+        let expected = [
+            (
+                Orientation::TopLeft,
+                vec![vec![0, 5], vec![1, 4], vec![2, 3]],
+            ),
+            (
+                Orientation::TopRight,
+                vec![vec![2, 3], vec![1, 4], vec![0, 5]],
+            ),
+            (
+                Orientation::BottomLeft,
+                vec![vec![5, 0], vec![4, 1], vec![3, 2]],
+            ),
+            (
+                Orientation::BottomRight,
+                vec![vec![3, 2], vec![4, 1], vec![5, 0]],
+            ),
+        ];
+        for (orientation, layout_expected) in expected.iter() {
+            let map = AddrMap::from_mapping(opt(
+                Displacement::Snake,
+                Direction::Horizontal,
+                *orientation,
+            ));
+            assert_eq!(&layout(&map), layout_expected, "{:?}", orientation);
+        }
+    }
+
+    #[test]
+    fn direction_horizontal_progressive() {
+        let map = AddrMap::from_mapping(opt(
+            Displacement::Progressive,
+            Direction::Horizontal,
+            Orientation::TopLeft,
+        ));
+        assert_eq!(layout(&map), vec![vec![0, 3], vec![1, 4], vec![2, 5]]);
+    }
+
+    #[test]
+    fn direction_with_orientation_tall_univer() {
+        #[rustfmt::skip]
+        let expected = [
+            (Direction::Vertical, Orientation::TopLeft, vec![vec![0, 1, 2], vec![3, 4, 5]]),
+            (Direction::Vertical, Orientation::TopRight, vec![vec![3, 4, 5], vec![0, 1, 2]]),
+            (Direction::Vertical, Orientation::BottomLeft, vec![vec![2, 1, 0], vec![5, 4, 3]]),
+            (Direction::Vertical, Orientation::BottomRight, vec![vec![5, 4, 3], vec![2, 1, 0]]),
+            (Direction::Horizontal, Orientation::TopLeft, vec![vec![0, 2, 4], vec![1, 3, 5]]),
+            (Direction::Horizontal, Orientation::TopRight, vec![vec![1, 3, 5], vec![0, 2, 4]]),
+            (Direction::Horizontal, Orientation::BottomLeft, vec![vec![4, 2, 0], vec![5, 3, 1]]),
+            (Direction::Horizontal, Orientation::BottomRight, vec![vec![5, 3, 1], vec![4, 2, 0]]),
+        ];
+        for (direction, orientation, layout_expected) in expected.iter() {
+            // A single 2x3 univer
+            let mut opt = mapping(Displacement::Progressive, *direction, *orientation);
+            opt.width = 2;
+            opt.height = 3;
+            opt.univer_height = 3;
+            let map = AddrMap::from_mapping(opt.try_into().unwrap());
+            assert_eq!((map.opt.univer_width, map.opt.univer_height), (2, 3));
+            assert_eq!(
+                &layout(&map),
+                layout_expected,
+                "{:?} {:?}",
+                direction,
+                orientation
+            );
+        }
+    }
+
+    #[test]
+    fn direction_horizontal_cut_univers() {
+        // 3x2 univers on a 5x3 matrix: the last column of univers holds 2 pixels rows and the
+        // last row of univers holds a single row
+        let mut opt = mapping(
+            Displacement::Progressive,
+            Direction::Horizontal,
+            Orientation::TopLeft,
+        );
+        opt.width = 5;
+        opt.height = 3;
+        opt.orientation = vec![Orientation::TopLeft; 4];
+        let opt = MappingOptExt::try_from(opt).unwrap();
+        assert_eq!((opt.univer_per_column, opt.univer_per_row), (2, 2));
+        #[rustfmt::skip]
+        assert_eq!(
+            rows(&AddrMap::from_mapping(opt)),
+            vec![
+                vec![(0, 0), (0, 1), (0, 2), (1, 0), (1, 1)],
+                vec![(0, 3), (0, 4), (0, 5), (1, 2), (1, 3)],
+                vec![(2, 0), (2, 1), (2, 2), (3, 0), (3, 1)],
+            ]
+        );
+    }
+
+    #[test]
+    fn direction_vertical_cut_univers() {
+        // 3x2 univers on a 5x2 matrix: the last univer holds 2 columns
+        let mut opt = mapping(
+            Displacement::Progressive,
+            Direction::Vertical,
+            Orientation::TopRight,
+        );
+        opt.width = 5;
+        opt.orientation = vec![Orientation::TopRight; 2];
+        #[rustfmt::skip]
+        assert_eq!(
+            rows(&AddrMap::from_mapping(opt.clone().try_into().unwrap())),
+            vec![
+                vec![(0, 4), (0, 2), (0, 0), (1, 2), (1, 0)],
+                vec![(0, 5), (0, 3), (0, 1), (1, 3), (1, 1)],
+            ]
+        );
+        // Columns can't be cut
+        opt.height = 3;
+        assert!(MappingOptExt::try_from(opt).is_err());
+    }
+
+    #[test]
+    fn direction_names() {
+        let parse = |name: &str| serde_json::from_str::<Direction>(&format!("\"{}\"", name));
+        assert_eq!(parse("Vertical").unwrap(), Direction::Vertical);
+        assert_eq!(parse("ColumnFirst").unwrap(), Direction::Vertical);
+        assert_eq!(parse("Horizontal").unwrap(), Direction::Horizontal);
+        assert_eq!(parse("RowFirst").unwrap(), Direction::Horizontal);
+        assert!(parse("Diagonal").is_err());
+        assert_eq!(
+            serde_json::to_string(&Direction::Horizontal).unwrap(),
+            "\"Horizontal\""
+        );
+    }
+
+    fn valid_opt() -> MappingOpt {
+        MappingOpt {
+            dmx_size: 400,
+            width: 40,
+            height: 20,
+            univer_height: 10,
+            color_mode: ColorMode::RGBA,
+            displacement: Displacement::Snake,
+            direction: Direction::Vertical,
+            orientation: vec![Orientation::TopLeft; 8],
+            start_address: 0,
+            port_addresses: None,
+            channels: None,
+            color_depth: ColorDepth::Bit8,
+            correction: CorrectionOpt::default(),
+            power: PowerOpt::default(),
+            dithering: false,
+            pacing: PacingOpt::default(),
+            nodes: None,
+            protocol: Protocol::Artnet,
+            artnet: ArtnetOpt::default(),
+        }
+    }
+
+    #[test]
+    fn try_from_valid() {
+        let opt = MappingOptExt::try_from(valid_opt()).unwrap();
+        assert_eq!(opt.univer_width, 10);
+        assert_eq!(opt.univer_per_column, 4);
+        assert_eq!(opt.univer_per_row, 2);
+    }
+
+    #[test]
+    fn try_from_zero_size() {
+        let mut opt = valid_opt();
+        opt.width = 0;
+        match MappingOptExt::try_from(opt) {
+            Err(GError::ZeroSize { field: "width" }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_from_univer_too_large() {
+        let mut opt = valid_opt();
+        opt.dmx_size = 513;
+        match MappingOptExt::try_from(opt) {
+            Err(GError::UniverTooLarge {
+                dmx_size: 513,
+                max: 512,
+            }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_from_univer_height_too_large() {
+        let mut opt = valid_opt();
+        opt.univer_height = 101;
+        opt.height = 101;
+        match MappingOptExt::try_from(opt) {
+            Err(GError::UniverHeightTooLarge {
+                univer_height: 101,
+                pixel_per_univer: 100,
+            }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_from_unaligned_height() {
+        let mut opt = valid_opt();
+        opt.height = 25;
+        match MappingOptExt::try_from(opt) {
+            Err(GError::UnalignedHeight {
+                height: 25,
+                univer_height: 10,
+            }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_from_missing_orientation() {
+        let mut opt = valid_opt();
+        opt.orientation.truncate(7);
+        match MappingOptExt::try_from(opt) {
+            Err(GError::MissingOrientation {
+                expected: 8,
+                found: 7,
+            }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_from_start_address() {
+        let mut opt = valid_opt();
+        opt.start_address = 0x10;
+        let opt = MappingOptExt::try_from(opt).unwrap();
+        assert_eq!(
+            opt.port_addresses,
+            vec![0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17]
+        );
+    }
+
+    #[test]
+    fn try_from_explicit_port_addresses() {
+        let mut opt = valid_opt();
+        opt.start_address = 0x10;
+        opt.port_addresses = Some(vec![0, 1, 4, 5, 2, 3, 6, 7]);
+        let opt = MappingOptExt::try_from(opt).unwrap();
+        assert_eq!(opt.port_addresses, vec![0, 1, 4, 5, 2, 3, 6, 7]);
+    }
+
+    #[test]
+    fn try_from_invalid_port_address() {
+        let mut opt = valid_opt();
+        opt.start_address = MAX_PORT_ADDRESS - 2;
+        match MappingOptExt::try_from(opt) {
+            Err(GError::InvalidPortAddress { address: 0x8000 }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut opt = valid_opt();
+        opt.port_addresses = Some(vec![0, 1, 2, 3, 4, 5, 6, 0x8000]);
+        match MappingOptExt::try_from(opt) {
+            Err(GError::InvalidPortAddress { address: 0x8000 }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_from_protocol_nodes() {
+        let mut opt = valid_opt();
+        opt.start_address = 0x100;
+        opt.protocol = Protocol::E131(E131Opt::default());
+        let nodes = MappingOptExt::try_from(opt.clone()).unwrap().nodes;
+        assert_eq!(nodes[0], SocketAddr::from(([239, 255, 1, 1], 5568)));
+        assert_eq!(nodes[7], SocketAddr::from(([239, 255, 1, 8], 5568)));
+        opt.protocol = Protocol::Ddp;
+        match MappingOptExt::try_from(opt.clone()) {
+            Err(GError::MissingNode {
+                expected: 8,
+                found: 0,
+            }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        // DDP univers are not limited to 512 channels
+        opt.dmx_size = 1200;
+        opt.nodes = Some(vec![SocketAddr::from(([10, 0, 0, 1], DDP_PORT)); 4]);
+        assert_eq!(MappingOptExt::try_from(opt).unwrap().univer_width, 30);
+    }
+
+    #[test]
+    fn try_from_nodes() {
+        let mut opt = valid_opt();
+        let node = SocketAddr::from(([10, 0, 0, 18], ARTNET_PORT));
+        opt.nodes = Some(vec![node; 7]);
+        match MappingOptExt::try_from(opt.clone()) {
+            Err(GError::MissingNode {
+                expected: 8,
+                found: 7,
+            }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        opt.nodes = Some(vec![node; 8]);
+        assert_eq!(MappingOptExt::try_from(opt).unwrap().nodes, vec![node; 8]);
+    }
+
+    #[test]
+    fn try_from_artnet_sync() {
+        let mut opt = valid_opt();
+        opt.artnet.sync = true;
+        assert!(MappingOptExt::try_from(opt.clone()).is_ok());
+        opt.pacing.keep_alive = ARTSYNC_TIMEOUT;
+        assert!(MappingOptExt::try_from(opt.clone()).is_err());
+        // ArtSync is only sent by the Art-Net serializer
+        opt.protocol = Protocol::Ola;
+        assert!(MappingOptExt::try_from(opt).is_ok());
+    }
+
+    #[test]
+    fn try_from_missing_port_address() {
+        let mut opt = valid_opt();
+        opt.port_addresses = Some(vec![0, 1]);
+        match MappingOptExt::try_from(opt) {
+            Err(GError::MissingPortAddress {
+                expected: 8,
+                found: 2,
+            }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_from_channels() {
+        let mut opt = valid_opt();
+        opt.channels = Some(vec![Channel::Green, Channel::Red, Channel::Blue]);
+        let opt = MappingOptExt::try_from(opt).unwrap();
+        assert_eq!(opt.pixel_size, 3);
+        assert_eq!(opt.input_pixel_size, 4);
+        assert_eq!(opt.univer_width, 13);
+    }
+
+    #[test]
+    fn try_from_color_depth() {
+        let mut opt = valid_opt();
+        opt.color_depth = ColorDepth::Bit16;
+        opt.orientation = vec![Orientation::TopLeft; 16];
+        let opt = MappingOptExt::try_from(opt).unwrap();
+        assert_eq!(opt.pixel_size, 8);
+        assert_eq!(opt.univer_width, 5);
+        assert_eq!(opt.univer_per_column, 8);
+    }
+
+    #[test]
+    fn try_from_invalid_correction() {
+        let mut opt = valid_opt();
+        opt.correction.gamma = 0.0;
+        match MappingOptExt::try_from(opt) {
+            Err(GError::InvalidGamma { .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut opt = valid_opt();
+        opt.correction.white_balance = [1.0, 1.5, 1.0];
+        match MappingOptExt::try_from(opt) {
+            Err(GError::InvalidWhiteBalance { .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_from_color_mode() {
+        let mut opt = valid_opt();
+        opt.color_mode = ColorMode::GRB;
+        let opt = MappingOptExt::try_from(opt).unwrap();
+        assert_eq!(opt.pixel_size, 3);
+        assert_eq!(
+            opt.channels,
+            vec![Channel::Green, Channel::Red, Channel::Blue]
+        );
+        let mut opt = valid_opt();
+        opt.color_mode = ColorMode::Dimmer;
+        opt.dmx_size = 40;
+        opt.orientation = vec![Orientation::TopLeft; 20];
+        let opt = MappingOptExt::try_from(opt).unwrap();
+        assert_eq!(opt.pixel_size, 1);
+        assert_eq!(opt.univer_width, 4);
+    }
+}
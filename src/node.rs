@@ -0,0 +1,516 @@
+//!
+//! Art-Net node management
+//!
+//! A node output port is addressed by its Net (bits 14-8), SubNet (bits 7-4) and SwOut
+//! (bits 3-0) switches, the Net and SubNet being shared by the ports of a bind index.
+//! `discover` collects the `ArtPollReply` of the nodes answering an `ArtPoll` and
+//! `address_programs` makes the `ArtAddress` packets reprogramming the discovered nodes so
+//! their output ports match the Port-Address of the univers routed to them
+//!
+
+use crate::prelude::*;
+use artnet_protocol::{Address, AddressSwitch, ArtCommand, Poll, PollReply};
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// Number of output ports of a bind index
+pub const PORTS_PER_BIND_INDEX: usize = 4;
+/// Nodes answer an ArtPoll within 3 seconds
+pub const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+/// Number of consecutive polls a node can miss before it is considered gone
+pub const MISSED_POLLS: u32 = 3;
+
+/// Art-Net node (or bind index of a node) found by a discovery
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    /// Address the node replied from
+    pub addr: SocketAddr,
+    /// Order of the node within its device, 1 for the root device
+    pub bind_index: u8,
+    pub short_name: String,
+    pub long_name: String,
+    /// Index (0 to 3) of each output port within the bind index
+    pub ports: Vec<usize>,
+    /// Port-Address of each output port
+    pub port_addresses: Vec<u16>,
+}
+
+fn read_name(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+impl Node {
+    pub fn from_poll_reply(reply: &PollReply, addr: SocketAddr) -> Self {
+        let net = u16::from(reply.port_address[0] & 0x7F);
+        let sub_net = u16::from(reply.port_address[1] & 0x0F);
+        let ports: Vec<usize> = (0..(reply.num_ports[1] as usize).min(PORTS_PER_BIND_INDEX))
+            // Bit 7 of the port type is set when the port can output DMX
+            .filter(|&port| reply.port_types[port] & 0x80 != 0)
+            .collect();
+        Self {
+            addr,
+            bind_index: reply.bind_index,
+            short_name: read_name(&reply.short_name),
+            long_name: read_name(&reply.long_name),
+            port_addresses: ports
+                .iter()
+                .map(|&port| net << 8 | sub_net << 4 | u16::from(reply.swout[port] & 0x0F))
+                .collect(),
+            ports,
+        }
+    }
+
+    /// A node is identified by its IP address and bind index
+    fn key(&self) -> (IpAddr, u8) {
+        (self.addr.ip(), self.bind_index)
+    }
+}
+
+/// Send an ArtPoll to `broadcast` and collect the nodes replying before `timeout`, the
+/// packets that are not an ArtPollReply (like the ArtPoll itself) are ignored
+pub fn discover(
+    transport: &mut UdpTransport,
+    broadcast: &SocketAddr,
+    timeout: Duration,
+) -> Result<Vec<Node>, GError> {
+    let poll = ArtCommand::Poll(Poll::default()).into_buffer()?;
+    transport.send_to(&poll, broadcast)?;
+    let deadline = Instant::now() + timeout;
+    let mut nodes: Vec<Node> = vec![];
+    let mut buffer = [0; 1024];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        transport.socket().set_read_timeout(Some(deadline - now))?;
+        let (length, addr) = match transport.socket().recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+            Err(e) => return Err(e.into()),
+        };
+        let node = match ArtCommand::from_buffer(&buffer[..length]) {
+            Ok(ArtCommand::PollReply(reply)) => Node::from_poll_reply(&reply, addr),
+            _ => continue,
+        };
+        // A node replying several times is only kept once, with its last reply
+        match nodes.iter_mut().find(|known| known.key() == node.key()) {
+            Some(known) => *known = node,
+            None => nodes.push(node),
+        }
+    }
+    transport.socket().set_read_timeout(None)?;
+    Ok(nodes)
+}
+
+/// Change of the nodes found by a `Discovery`
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeEvent {
+    Appeared(Node),
+    /// The node did not reply to the last `MISSED_POLLS` polls
+    Disappeared(Node),
+}
+
+/// Periodic discovery tracking the nodes coming and going
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    broadcast: SocketAddr,
+    timeout: Duration,
+    /// Known nodes and the number of consecutive polls they missed
+    nodes: Vec<(Node, u32)>,
+}
+
+impl Discovery {
+    pub fn new(broadcast: SocketAddr, timeout: Duration) -> Self {
+        Self {
+            broadcast,
+            timeout,
+            nodes: vec![],
+        }
+    }
+
+    /// Nodes currently known
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter().map(|(node, _)| node)
+    }
+
+    /// Poll the network and report the nodes that appeared or disappeared since the
+    /// previous polls
+    pub fn poll(&mut self, transport: &mut UdpTransport) -> Result<Vec<NodeEvent>, GError> {
+        let replies = discover(transport, &self.broadcast, self.timeout)?;
+        Ok(self.update(replies))
+    }
+
+    /// Update the known nodes with the replies of a poll
+    pub fn update(&mut self, replies: Vec<Node>) -> Vec<NodeEvent> {
+        let mut events = vec![];
+        for (node, missed) in self.nodes.iter_mut() {
+            *missed += 1;
+            if let Some(reply) = replies.iter().find(|reply| reply.key() == node.key()) {
+                *node = reply.clone();
+                *missed = 0;
+            }
+        }
+        for reply in replies {
+            if !self.nodes.iter().any(|(node, _)| node.key() == reply.key()) {
+                events.push(NodeEvent::Appeared(reply.clone()));
+                self.nodes.push((reply, 0));
+            }
+        }
+        let (gone, nodes) = self
+            .nodes
+            .drain(..)
+            .partition(|(_, missed)| *missed >= MISSED_POLLS);
+        self.nodes = nodes;
+        events.extend(
+            gone.into_iter()
+                .map(|(node, _): (Node, u32)| NodeEvent::Disappeared(node)),
+        );
+        events
+    }
+}
+
+/// `ArtAddress` packets programming the output ports of the discovered `nodes` with the
+/// Port-Address of the univers routed to them. The univers of a node address are taken in
+/// the configuration order and spread over its bind indexes, the ports of a bind index
+/// sharing the same Net and SubNet
+pub fn address_programs(
+    opt: &MappingOptExt,
+    nodes: &[Node],
+) -> Result<Vec<(SocketAddr, Address)>, GError> {
+    if opt.protocol != Protocol::Artnet {
+        return Err(GError::WrongConfig("only Art-Net nodes can be programmed"));
+    }
+    let mut programs = vec![];
+    let mut addrs: Vec<SocketAddr> = vec![];
+    for node in nodes {
+        if !addrs.contains(&node.addr) {
+            addrs.push(node.addr);
+        }
+    }
+    for addr in addrs {
+        let mut port_addresses: Vec<u16> = vec![];
+        for (port_address, node) in opt.port_addresses.iter().zip(opt.nodes.iter()) {
+            if *node == addr && !port_addresses.contains(port_address) {
+                port_addresses.push(*port_address);
+            }
+        }
+        let mut bind_indexes: Vec<&Node> = nodes.iter().filter(|node| node.addr == addr).collect();
+        bind_indexes.sort_by_key(|node| node.bind_index);
+        let mut ports = port_addresses.as_slice();
+        for node in bind_indexes {
+            let first = match ports.first() {
+                Some(&first) => first,
+                None => break,
+            };
+            let count = ports
+                .iter()
+                .take(node.ports.len())
+                .take_while(|port_address| *port_address >> 4 == first >> 4)
+                .count();
+            if count == 0 {
+                continue;
+            }
+            let mut address = Address {
+                bind_index: node.bind_index,
+                net_switch: AddressSwitch::Program((first >> 8) as u8),
+                sub_switch: AddressSwitch::Program((first >> 4 & 0xF) as u8),
+                ..Address::default()
+            };
+            for (port, port_address) in node.ports.iter().zip(&ports[..count]) {
+                address.swout[*port] = AddressSwitch::Program((port_address & 0xF) as u8);
+            }
+            programs.push((addr, address));
+            ports = &ports[count..];
+        }
+        if !ports.is_empty() {
+            return Err(GError::MissingNodePort {
+                node: addr,
+                missing: ports.len(),
+            });
+        }
+    }
+    Ok(programs)
+}
+
+/// Send the `ArtAddress` packets programming the discovered `nodes`
+pub fn program_nodes<T: Transport>(
+    transport: &mut T,
+    opt: &MappingOptExt,
+    nodes: &[Node],
+) -> Result<(), GError> {
+    for (addr, address) in address_programs(opt, nodes)? {
+        let packet = ArtCommand::Address(address).into_buffer()?;
+        transport.send_to(&packet, &addr)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn opt(port_addresses: Vec<u16>, nodes: Vec<SocketAddr>) -> MappingOptExt {
+        MappingOptExt::try_from(MappingOpt {
+            dmx_size: 12,
+            width: 2 * port_addresses.len(),
+            height: 2,
+            univer_height: 2,
+            color_mode: ColorMode::RGB,
+            displacement: Displacement::Progressive,
+            direction: Direction::Vertical,
+            orientation: vec![Orientation::TopLeft; port_addresses.len()],
+            start_address: 0,
+            port_addresses: Some(port_addresses),
+            channels: None,
+            color_depth: ColorDepth::Bit8,
+            correction: CorrectionOpt::default(),
+            power: PowerOpt::default(),
+            dithering: false,
+            pacing: PacingOpt::default(),
+            nodes: Some(nodes),
+            protocol: Protocol::Artnet,
+            artnet: ArtnetOpt::default(),
+        })
+        .unwrap()
+    }
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    /// Bind index of the node at `addr` outputting on `ports`
+    fn node(addr: SocketAddr, bind_index: u8, ports: Vec<usize>) -> Node {
+        Node {
+            addr,
+            bind_index,
+            short_name: String::new(),
+            long_name: String::new(),
+            port_addresses: vec![0; ports.len()],
+            ports,
+        }
+    }
+
+    #[test]
+    fn ports_are_grouped_by_subnet() {
+        let device = addr("10.0.0.1:6454");
+        let other = addr("10.0.0.2:6454");
+        let opt = opt(
+            vec![
+                0x0123, 0x0120, 0x0121, 0x0122, 0x0120, 0x0124, 0x0131, 0x0000,
+            ],
+            vec![
+                device, device, device, device, device, device, device, other,
+            ],
+        );
+        // Bind indexes are filled in order, whatever the order of the discovery
+        let nodes = vec![
+            node(device, 3, vec![0, 1, 2, 3]),
+            node(device, 1, vec![0, 1, 2, 3]),
+            node(device, 2, vec![0, 2]),
+        ];
+        let programs = address_programs(&opt, &nodes).unwrap();
+        let program = |bind_index: u8, net: u8, sub: u8, swout: [AddressSwitch; 4]| Address {
+            bind_index,
+            net_switch: AddressSwitch::Program(net),
+            sub_switch: AddressSwitch::Program(sub),
+            swout,
+            ..Address::default()
+        };
+        use AddressSwitch::{NoChange, Program};
+        // The univers keep the configuration order
+        let expected = [
+            program(1, 1, 2, [Program(3), Program(0), Program(1), Program(2)]),
+            program(2, 1, 2, [Program(4), NoChange, NoChange, NoChange]),
+            program(3, 1, 3, [Program(1), NoChange, NoChange, NoChange]),
+        ];
+        assert_eq!(programs.len(), expected.len());
+        for ((node, program), expected) in programs.iter().zip(expected.iter()) {
+            assert_eq!(*node, device);
+            assert_eq!(program.bind_index, expected.bind_index);
+            assert_eq!(program.net_switch, expected.net_switch);
+            assert_eq!(program.sub_switch, expected.sub_switch);
+            assert_eq!(program.swout, expected.swout);
+            assert_eq!(program.swin, [NoChange; 4]);
+        }
+    }
+
+    #[test]
+    fn programs_use_output_ports() {
+        let device = addr("10.0.0.1:6454");
+        let opt = opt(vec![0x10, 0x11], vec![device, device]);
+        // Port 1 is an input
+        let programs = address_programs(&opt, &[node(device, 1, vec![0, 2])]).unwrap();
+        use AddressSwitch::{NoChange, Program};
+        assert_eq!(
+            programs[0].1.swout,
+            [Program(0), NoChange, Program(1), NoChange]
+        );
+    }
+
+    #[test]
+    fn univers_must_fit_into_node_ports() {
+        let device = addr("10.0.0.1:6454");
+        let opt = opt(vec![0x10, 0x11, 0x20], vec![device; 3]);
+        match address_programs(&opt, &[node(device, 1, vec![0, 1, 2, 3])]) {
+            Err(GError::MissingNodePort { node, missing: 1 }) => assert_eq!(node, device),
+            other => panic!("unexpected {:?}", other),
+        }
+        match address_programs(&opt, &[node(device, 1, vec![0])]) {
+            Err(GError::MissingNodePort { missing: 2, .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        let nodes = [node(device, 1, vec![0, 1]), node(device, 2, vec![0])];
+        assert_eq!(address_programs(&opt, &nodes).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn program_sends_address_packets() {
+        let device = addr("10.0.0.1:6454");
+        let opt = opt(vec![0x10, 0x11], vec![device, device]);
+        let transport = LoopbackTransport::new();
+        program_nodes(
+            &mut transport.clone(),
+            &opt,
+            &[node(device, 1, vec![0, 1, 2, 3])],
+        )
+        .unwrap();
+        let packets = transport.take();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].0, device);
+        match ArtCommand::from_buffer(&packets[0].1).unwrap() {
+            ArtCommand::Address(address) => {
+                assert_eq!(address.sub_switch, AddressSwitch::Program(1));
+                assert_eq!(address.swout[1], AddressSwitch::Program(1));
+            }
+            command => panic!("expected an ArtAddress packet, got {:?}", command),
+        }
+    }
+
+    fn poll_reply(bind_index: u8, port_address: [u8; 2], swout: [u8; 4]) -> Vec<u8> {
+        let mut short_name = [0; 18];
+        short_name[..4].copy_from_slice(b"node");
+        let reply = PollReply {
+            address: [10, 0, 0, 1].into(),
+            port: ARTNET_PORT,
+            version: [0, 14],
+            port_address,
+            oem: [0; 2],
+            ubea_version: 0,
+            status_1: 0,
+            esta_code: 0,
+            short_name,
+            long_name: [0; 64],
+            node_report: [0; 64],
+            num_ports: [0, 3],
+            port_types: [0x80, 0x40, 0x80, 0x80],
+            good_input: [0; 4],
+            good_output: [0; 4],
+            swin: [0; 4],
+            swout,
+            sw_video: 0,
+            sw_macro: 0,
+            sw_remote: 0,
+            spare: [0; 3],
+            style: 0,
+            mac: [0; 6],
+            bind_ip: [10, 0, 0, 1],
+            bind_index,
+            status_2: 0,
+        };
+        ArtCommand::PollReply(Box::new(reply))
+            .into_buffer()
+            .unwrap()
+    }
+
+    #[test]
+    fn discover_collects_replies() {
+        let node = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let node_addr = node.local_addr().unwrap();
+        let mut transport = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let replier = std::thread::spawn(move || {
+            let mut buffer = [0; 1024];
+            let (length, controller) = node.recv_from(&mut buffer).unwrap();
+            match ArtCommand::from_buffer(&buffer[..length]).unwrap() {
+                ArtCommand::Poll(_) => {}
+                command => panic!("expected an ArtPoll packet, got {:?}", command),
+            }
+            for packet in [
+                poll_reply(1, [0x01, 0x02], [1, 2, 3, 4]),
+                // Packets that are not a reply
+                buffer[..length].to_vec(),
+                vec![0xFF; 12],
+                poll_reply(2, [0x01, 0x03], [5, 6, 7, 8]),
+                // Repeated reply
+                poll_reply(1, [0x01, 0x02], [1, 2, 3, 4]),
+            ]
+            .iter()
+            {
+                node.send_to(packet, controller).unwrap();
+            }
+        });
+        let nodes = discover(&mut transport, &node_addr, Duration::from_millis(500)).unwrap();
+        replier.join().unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                Node {
+                    addr: node_addr,
+                    bind_index: 1,
+                    short_name: String::from("node"),
+                    long_name: String::new(),
+                    ports: vec![0, 2],
+                    port_addresses: vec![0x0121, 0x0123],
+                },
+                Node {
+                    addr: node_addr,
+                    bind_index: 2,
+                    short_name: String::from("node"),
+                    long_name: String::new(),
+                    ports: vec![0, 2],
+                    port_addresses: vec![0x0135, 0x0137],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn discovery_tracks_nodes() {
+        let node = |ip: [u8; 4]| node(SocketAddr::from((ip, ARTNET_PORT)), 1, vec![0]);
+        let (first, second) = (node([10, 0, 0, 1]), node([10, 0, 0, 2]));
+        let mut discovery = Discovery::new(addr("255.255.255.255:6454"), DISCOVERY_TIMEOUT);
+        assert_eq!(
+            discovery.update(vec![first.clone()]),
+            vec![NodeEvent::Appeared(first.clone())]
+        );
+        assert_eq!(
+            discovery.update(vec![second.clone()]),
+            vec![NodeEvent::Appeared(second.clone())]
+        );
+        // A node is only gone after missing several polls
+        assert!(discovery.update(vec![second.clone()]).is_empty());
+        assert_eq!(discovery.nodes().count(), 2);
+        assert_eq!(
+            discovery.update(vec![second.clone()]),
+            vec![NodeEvent::Disappeared(first)]
+        );
+        assert_eq!(discovery.nodes().collect::<Vec<_>>(), vec![&second]);
+        assert!(discovery.update(vec![]).is_empty());
+        assert!(discovery.update(vec![second.clone()]).is_empty());
+        assert_eq!(discovery.nodes().count(), 1);
+    }
+
+    #[test]
+    fn only_artnet_nodes_are_programmed() {
+        let device = addr("10.0.0.1:6454");
+        let mut opt = opt(vec![0, 1], vec![device, device]);
+        opt.protocol = Protocol::Ddp;
+        assert!(address_programs(&opt, &[node(device, 1, vec![0, 1])]).is_err());
+    }
+}
@@ -5,13 +5,47 @@
 //!
 
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
-/// Used to derterminate number of address used by one pixel and image buffer parsing
-#[repr(usize)]
-#[derive(Debug, Clone, Serialize, Deserialize, Copy)]
+/// Native channels order of the fixture, used to derterminate number of address used by one pixel
+/// (input buffers are always RGBA and are converted into this order)
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq)]
 pub enum ColorMode {
-    RGBA = 4,
-    RGB = 3,
+    RGBA,
+    RGB,
+    GRB,
+    BRG,
+    RGBW,
+    GRBW,
+    WRGB,
+    /// Single white channel
+    W,
+    /// Single dimmer channel driven by the pixel luminance
+    Dimmer,
+}
+
+/// Number of bits of a fixture channel, 16 bits channels use two DMX slots (coarse then fine)
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq, Default)]
+pub enum ColorDepth {
+    #[default]
+    Bit8,
+    Bit16,
+}
+
+/// Source of a fixture channel, computed from the input pixel
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    /// White derived from the input pixel (smallest of its red, green and blue values)
+    White,
+    /// Luminance of the input pixel
+    Luminance,
+    /// Channel always sent as zero
+    Zero,
 }
 
 /// Used to determinate position of the first led of each univer
@@ -23,15 +57,26 @@ pub enum Orientation {
     BottomRight,
 }
 
+/// Used to determinate how leds are wired inside of an univer
 #[derive(Debug, Clone, Serialize, Deserialize, Copy)]
 pub enum Displacement {
+    /// Every line starts from the same edge of the univer
+    Progressive,
+    /// Every odd line is reversed (serpentine wiring)
     Snake,
+    /// Leds follow the univer diagonals, alternating the direction on each diagonal
+    /// (serpentine strip of a panel mounted rotated by 45 degrees)
     ZigZag,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Copy)]
+/// Used to determinate if the lines of an univer are its columns or its rows
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq)]
 pub enum Direction {
+    /// Leds are wired row first
+    #[serde(alias = "RowFirst")]
     Horizontal,
+    /// Leds are wired column first
+    #[serde(alias = "ColumnFirst")]
     Vertical,
 }
 
@@ -49,8 +94,224 @@ pub struct MappingOpt {
     // Height of individual chunk/univers)  width is determinated b dmx_size
     pub univer_height: usize,
     /// Color mode, nbr channel per pixel is determinated from this, example: RGBA take 4 address and RGB take 3
+    /// the input buffer is always RGBA whatever the color mode
     pub color_mode: ColorMode,
     pub displacement: Displacement,
     pub direction: Direction,
     pub orientation: Vec<Orientation>,
+    /// Art-Net Port-Address (15 bits) of the first univer, following univers use the next addresses
+    #[serde(default)]
+    pub start_address: u16,
+    /// Explicit Art-Net Port-Address of each univer, overrides `start_address` when provided
+    #[serde(default)]
+    pub port_addresses: Option<Vec<u16>>,
+    /// Channels sent to the fixture for each pixel (in fixture order), the input pixel is sent
+    /// as is when not provided. ex: `["Green", "Red", "Blue"]` drops alpha and swap red/green
+    #[serde(default)]
+    pub channels: Option<Vec<Channel>>,
+    /// Depth of each fixture channel (default `Bit8`)
+    #[serde(default)]
+    pub color_depth: ColorDepth,
+    /// Color correction applied to input frames before encoding (disabled by default)
+    #[serde(default)]
+    pub correction: CorrectionOpt,
+    /// Master brightness and current limit (disabled by default)
+    #[serde(default)]
+    pub power: PowerOpt,
+    /// Temporal dithering of 8 bits channels (ignored with `Bit16` color depth)
+    #[serde(default)]
+    pub dithering: bool,
+    /// Output refresh rate used by `Screen::present`
+    #[serde(default)]
+    pub pacing: PacingOpt,
+    /// Address of the node of each univer (ex: `"10.0.0.18:6454"`), univers are broadcast
+    /// when not provided
+    #[serde(default)]
+    pub nodes: Option<Vec<SocketAddr>>,
+    /// Output protocol (default `Artnet`)
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// Art-Net output settings
+    #[serde(default)]
+    pub artnet: ArtnetOpt,
+}
+
+///
+/// Art-Net controller settings
+///
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ArtnetOpt {
+    /// Send an ArtSync after the ArtDmx packets of each frame so the nodes latch every univer
+    /// at the same time, ignored while univers are broadcast
+    pub sync: bool,
+    /// Number ArtDmx packets (1 to 255 per Port-Address) so the nodes can reorder them,
+    /// the sequence is always 0 when disabled
+    pub sequence: bool,
+}
+
+impl Default for ArtnetOpt {
+    fn default() -> Self {
+        Self {
+            sync: false,
+            sequence: true,
+        }
+    }
+}
+
+/// Protocol used to send univers to the nodes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub enum Protocol {
+    #[default]
+    Artnet,
+    /// Streaming ACN, univers are sent to their multicast address when `nodes` is not provided
+    E131(E131Opt),
+    /// Distributed Display Protocol, `dmx_size` is not limited to 512 and the univers sent to
+    /// a node are concatenated into a single pixel stream (`nodes` is required)
+    Ddp,
+    /// Open Pixel Control over TCP, each univer is an OPC channel (8 bits RGB pixels only),
+    /// univers are sent to a local server when `nodes` is not provided
+    Opc,
+    /// Enttec DMX USB Pro serial widget, the matrix must fit into a single univer
+    /// (`nodes` is not used)
+    Enttec(EnttecOpt),
+    /// OLA daemon RPC, each univer is sent to the OLA univer Port-Address and patching stays
+    /// in OLA, univers are sent to a local olad when `nodes` is not provided
+    Ola,
+}
+
+///
+/// Color correction of the red, green and blue channels, alpha is never corrected
+///
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct CorrectionOpt {
+    /// Exponent of the gamma curve, ex: 2.2 (1.0 disables the curve)
+    pub gamma: f32,
+    /// Red, green and blue multipliers applied after the gamma curve (between 0.0 and 1.0)
+    pub white_balance: [f32; 3],
+}
+
+impl Default for CorrectionOpt {
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            white_balance: [1.0; 3],
+        }
+    }
+}
+
+///
+/// Master brightness and current limit applied to every frame after the color correction
+///
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PowerOpt {
+    /// Master brightness between 0.0 and 1.0
+    pub brightness: f32,
+    /// Frames are scaled down when their estimated current draw exceed this limit
+    pub current_limit: Option<CurrentLimit>,
+}
+
+impl Default for PowerOpt {
+    fn default() -> Self {
+        Self {
+            brightness: 1.0,
+            current_limit: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CurrentLimit {
+    /// Current (mA) drawn by one fixture channel at full level
+    pub channel_current: f32,
+    pub budget: PowerBudget,
+}
+
+/// Maximum current (mA) a power supply can deliver
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum PowerBudget {
+    /// Each univer has its own power supply
+    PerUniver(f32),
+    /// A single power supply feeds the whole matrix
+    Matrix(f32),
+}
+
+///
+/// Output rate of `Screen::present`, source frames are repeated or dropped to follow it
+///
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PacingOpt {
+    /// Target output rate (Hz)
+    pub refresh_rate: f32,
+    /// Maximum rate the fixtures can take (Hz), a full DMX512 univer can't exceed 44 Hz
+    pub max_rate: f32,
+    /// Delay (ms) after which the output thread re-sends the last frame when the source is idle,
+    /// Art-Net nodes expect a refresh every 800 to 1000 ms
+    pub keep_alive: u64,
+}
+
+impl Default for PacingOpt {
+    fn default() -> Self {
+        Self {
+            refresh_rate: 40.0,
+            max_rate: 44.0,
+            keep_alive: 800,
+        }
+    }
+}
+
+///
+/// Streaming ACN (E1.31) source settings
+///
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct E131Opt {
+    /// Component identifier (UUID) of the source, a random one is generated when not provided
+    pub cid: Option<[u8; 16]>,
+    /// Name displayed by receivers (truncated to 63 bytes)
+    pub source_name: String,
+    /// Priority of the source between 0 and 200, receivers keep the highest priority source
+    pub priority: u8,
+    /// Data is meant for visualisation only and shouldn't be sent to the fixtures
+    pub preview: bool,
+}
+
+impl Default for E131Opt {
+    fn default() -> Self {
+        Self {
+            cid: None,
+            source_name: String::from("glola"),
+            priority: 100,
+            preview: false,
+        }
+    }
+}
+
+///
+/// Enttec DMX USB Pro widget settings
+///
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct EnttecOpt {
+    /// Serial device of the widget, ex: /dev/ttyUSB0
+    pub device: PathBuf,
+    /// Duration (microseconds) of the DMX break, rounded to 10.67 us units by the widget:
+    /// from 9 units (96.03 us) to 127 units (1355.09 us)
+    pub break_time: f32,
+    /// Duration (microseconds) of the mark after break, rounded to 10.67 us units by the
+    /// widget: from 1 unit (10.67 us) to 127 units (1355.09 us)
+    pub mab_time: f32,
+}
+
+impl Default for EnttecOpt {
+    fn default() -> Self {
+        Self {
+            device: PathBuf::from("/dev/ttyUSB0"),
+            break_time: 96.0,
+            mab_time: 10.67,
+        }
+    }
 }
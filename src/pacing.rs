@@ -0,0 +1,225 @@
+//!
+//! Frame pacing: output ticks are emitted at a fixed refresh rate whatever the source rate is,
+//! source frames are repeated or dropped to match it
+//!
+
+use crate::prelude::*;
+use std::time::{Duration, Instant};
+
+/// Sleeping is not precise enough, the last part of the wait is done by yielding
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+impl PacingOpt {
+    pub fn validate(&self) -> Result<(), GError> {
+        for &rate in [self.refresh_rate, self.max_rate].iter() {
+            if !rate.is_finite() || rate <= 0.0 {
+                return Err(GError::InvalidRefreshRate { rate });
+            }
+        }
+        Ok(())
+    }
+
+    /// Delay between two output ticks (the refresh rate is capped to the max rate)
+    pub fn interval(&self) -> Duration {
+        let rate = self.refresh_rate.min(self.max_rate);
+        Duration::from_nanos((1_000_000_000.0 / f64::from(rate)) as u64)
+    }
+}
+
+/// Statistics of the output ticks
+#[derive(Debug, Clone, Default)]
+pub struct PacingStats {
+    /// Source frames presented
+    pub frames: u64,
+    /// Output ticks emitted
+    pub ticks: u64,
+    /// Source frames skipped because no output tick happened while they were displayed
+    pub dropped: u64,
+    /// Extra output ticks emitted for source frames lasting more than one tick
+    pub repeated: u64,
+    /// Times the source was more than one tick late and the timeline was resynchronized
+    pub late: u64,
+    /// Delay between the scheduled and the actual output of the last tick
+    pub jitter: Duration,
+    pub max_jitter: Duration,
+    total_jitter: Duration,
+}
+
+impl PacingStats {
+    pub fn mean_jitter(&self) -> Duration {
+        if self.ticks == 0 {
+            return Duration::default();
+        }
+        Duration::from_nanos((self.total_jitter.as_nanos() / u128::from(self.ticks)) as u64)
+    }
+}
+
+/// Map the source timeline (each frame lasts its own duration) to output ticks
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    interval: Duration,
+    /// Start of the next source frame
+    source_time: Option<Instant>,
+    next_tick: Option<Instant>,
+    stats: PacingStats,
+}
+
+impl FramePacer {
+    pub fn new(opt: &PacingOpt) -> Self {
+        Self {
+            interval: opt.interval(),
+            source_time: None,
+            next_tick: None,
+            stats: PacingStats::default(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn stats(&self) -> &PacingStats {
+        &self.stats
+    }
+
+    /// Schedule a source frame displayed during `duration`, return the deadlines of the
+    /// output ticks it must be sent at (empty when the frame is dropped)
+    pub fn schedule(&mut self, duration: Duration, now: Instant) -> Vec<Instant> {
+        let (mut source_time, mut next_tick) = match (self.source_time, self.next_tick) {
+            (Some(source_time), Some(next_tick)) => (source_time, next_tick),
+            _ => (now, now),
+        };
+        if now > next_tick + self.interval {
+            self.stats.late += 1;
+            source_time = now;
+            next_tick = now;
+        }
+        let end = source_time + duration;
+        let mut ticks = vec![];
+        while next_tick < end {
+            ticks.push(next_tick);
+            next_tick += self.interval;
+        }
+        self.source_time = Some(end);
+        self.next_tick = Some(next_tick);
+        self.stats.frames += 1;
+        match ticks.len() {
+            0 => self.stats.dropped += 1,
+            len => self.stats.repeated += len as u64 - 1,
+        }
+        ticks
+    }
+
+    /// Record an output tick scheduled at `deadline` and sent at `sent`
+    pub fn record(&mut self, deadline: Instant, sent: Instant) {
+        let jitter = if sent > deadline {
+            sent - deadline
+        } else {
+            deadline - sent
+        };
+        self.stats.ticks += 1;
+        self.stats.jitter = jitter;
+        self.stats.max_jitter = self.stats.max_jitter.max(jitter);
+        self.stats.total_jitter += jitter;
+    }
+}
+
+/// Block until `deadline`
+pub fn wait_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now + SPIN_MARGIN {
+        std::thread::sleep(deadline - now - SPIN_MARGIN);
+    }
+    while Instant::now() < deadline {
+        std::thread::yield_now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pacer(refresh_rate: f32, max_rate: f32) -> FramePacer {
+        FramePacer::new(&PacingOpt {
+            refresh_rate,
+            max_rate,
+            ..PacingOpt::default()
+        })
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn interval_capped_to_max_rate() {
+        assert_eq!(pacer(40.0, 100.0).interval(), ms(25));
+        assert_eq!(pacer(100.0, 40.0).interval(), ms(25));
+    }
+
+    #[test]
+    fn slow_source_is_repeated() {
+        let mut pacer = pacer(40.0, 44.0);
+        let start = Instant::now();
+        let ticks = pacer.schedule(ms(100), start);
+        assert_eq!(
+            ticks,
+            vec![start, start + ms(25), start + ms(50), start + ms(75)]
+        );
+        let ticks = pacer.schedule(ms(100), start + ms(80));
+        assert_eq!(ticks[0], start + ms(100));
+        assert_eq!(pacer.stats().repeated, 6);
+        assert_eq!(pacer.stats().dropped, 0);
+    }
+
+    #[test]
+    fn fast_source_is_dropped() {
+        let mut pacer = pacer(40.0, 44.0);
+        let start = Instant::now();
+        let sent: Vec<usize> = (0..10)
+            .map(|_| pacer.schedule(ms(10), start).len())
+            .collect();
+        // Ticks at 0, 25, 50, 75 ms for frames starting at 0, 20, 50, 70 ms
+        assert_eq!(sent, vec![1, 0, 1, 0, 0, 1, 0, 1, 0, 0]);
+        assert_eq!(pacer.stats().frames, 10);
+        assert_eq!(pacer.stats().dropped, 6);
+    }
+
+    #[test]
+    fn late_source_is_resynchronized() {
+        let mut pacer = pacer(40.0, 44.0);
+        let start = Instant::now();
+        pacer.schedule(ms(25), start);
+        let ticks = pacer.schedule(ms(25), start + ms(200));
+        assert_eq!(ticks, vec![start + ms(200)]);
+        assert_eq!(pacer.stats().late, 1);
+    }
+
+    #[test]
+    fn jitter_statistics() {
+        let mut pacer = pacer(40.0, 44.0);
+        let start = Instant::now();
+        pacer.record(start, start + ms(2));
+        pacer.record(start, start + ms(4));
+        assert_eq!(pacer.stats().jitter, ms(4));
+        assert_eq!(pacer.stats().max_jitter, ms(4));
+        assert_eq!(pacer.stats().mean_jitter(), ms(3));
+    }
+
+    #[test]
+    fn validate() {
+        assert!(PacingOpt::default().validate().is_ok());
+        let opt = PacingOpt {
+            refresh_rate: 0.0,
+            ..PacingOpt::default()
+        };
+        assert!(opt.validate().is_err());
+    }
+
+    #[test]
+    fn wait_until_deadline() {
+        let deadline = Instant::now() + ms(5);
+        wait_until(deadline);
+        assert!(Instant::now() >= deadline);
+    }
+}
@@ -0,0 +1,216 @@
+//!
+//! Master brightness and current limiting applied to frames before encoding
+//!
+
+use crate::prelude::*;
+
+impl PowerOpt {
+    pub fn validate(&self) -> Result<(), GError> {
+        if !(0.0..=1.0).contains(&self.brightness) {
+            return Err(GError::InvalidBrightness {
+                brightness: self.brightness,
+            });
+        }
+        if let Some(limit) = &self.current_limit {
+            let budget = match limit.budget {
+                PowerBudget::PerUniver(budget) | PowerBudget::Matrix(budget) => budget,
+            };
+            for &current in [limit.channel_current, budget].iter() {
+                if !current.is_finite() || current <= 0.0 {
+                    return Err(GError::InvalidCurrent { current });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Scale frames down following a `PowerOpt`
+#[derive(Debug, Clone)]
+pub struct PowerLimiter {
+    opt: PowerOpt,
+    channels: Vec<Channel>,
+    /// Univer of each pixel of the input buffer
+    pixel_univers: Vec<usize>,
+    univer_count: usize,
+}
+
+impl PowerLimiter {
+    pub fn new(opt: PowerOpt, matrix: &AddrMap) -> Self {
+        let mut pixel_univers = vec![0; matrix.opt.width * matrix.opt.height];
+        for y in 0..matrix.opt.height {
+            for x in 0..matrix.opt.width {
+                pixel_univers[x + y * matrix.opt.width] = matrix.addr[x][y].univer;
+            }
+        }
+        Self {
+            opt,
+            channels: matrix.opt.channels.clone(),
+            pixel_univers,
+            univer_count: matrix.opt.univer_per_column * matrix.opt.univer_per_row,
+        }
+    }
+
+    pub fn opt(&self) -> &PowerOpt {
+        &self.opt
+    }
+
+    /// Estimated current draw (mA) of each univer for a 16 bits RGBA buffer
+    pub fn estimate(&self, buffer: &[u16], channel_current: f32) -> Vec<f32> {
+        let mut levels = vec![0u64; self.univer_count];
+        for (pixel, univer) in buffer.chunks(4).zip(self.pixel_univers.iter()) {
+            levels[*univer] += self
+                .channels
+                .iter()
+                .map(|channel| u64::from(channel.value(pixel)))
+                .sum::<u64>();
+        }
+        levels
+            .into_iter()
+            .map(|level| (level as f64 * f64::from(channel_current) / 65535.0) as f32)
+            .collect()
+    }
+
+    /// Scale `buffer` down following the brightness and the current limit,
+    /// return the factor applied by the current limit (1.0 when the frame is under budget)
+    pub fn apply(&self, buffer: &mut [u16]) -> f32 {
+        let brightness = self.opt.brightness;
+        let limit_scale = match &self.opt.current_limit {
+            None => 1.0,
+            Some(limit) => {
+                let draw = self.estimate(buffer, limit.channel_current);
+                let draw = match limit.budget {
+                    PowerBudget::PerUniver(budget) => draw
+                        .into_iter()
+                        .map(|draw| (draw * brightness, budget))
+                        .collect(),
+                    PowerBudget::Matrix(budget) => {
+                        vec![(draw.into_iter().sum::<f32>() * brightness, budget)]
+                    }
+                };
+                draw.into_iter()
+                    .filter(|(draw, budget)| draw > budget)
+                    .map(|(draw, budget)| budget / draw)
+                    .fold(1.0, f32::min)
+            }
+        };
+        let factor = brightness * limit_scale;
+        if factor < 1.0 {
+            // 16.16 fixed point so that a factor of 1.0 is lossless
+            let factor = (factor * 65536.0) as u32;
+            buffer
+                .iter_mut()
+                .for_each(|value| *value = ((u32::from(*value) * factor) >> 16) as u16);
+        }
+        limit_scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    /// 4x2 RGB matrix made of two univers of 2x2 pixels
+    fn matrix() -> AddrMap {
+        AddrMap::from_mapping(
+            MappingOptExt::try_from(MappingOpt {
+                dmx_size: 12,
+                width: 4,
+                height: 2,
+                univer_height: 2,
+                color_mode: ColorMode::RGB,
+                displacement: Displacement::Snake,
+                direction: Direction::Vertical,
+                orientation: vec![Orientation::TopLeft; 2],
+                start_address: 0,
+                port_addresses: None,
+                channels: None,
+                color_depth: ColorDepth::Bit8,
+                correction: CorrectionOpt::default(),
+                power: PowerOpt::default(),
+                dithering: false,
+                pacing: PacingOpt::default(),
+                nodes: None,
+                protocol: Protocol::Artnet,
+                artnet: ArtnetOpt::default(),
+            })
+            .unwrap(),
+        )
+    }
+
+    fn limiter(brightness: f32, budget: Option<PowerBudget>) -> PowerLimiter {
+        let opt = PowerOpt {
+            brightness,
+            current_limit: budget.map(|budget| CurrentLimit {
+                channel_current: 20.0,
+                budget,
+            }),
+        };
+        PowerLimiter::new(opt, &matrix())
+    }
+
+    #[test]
+    fn brightness() {
+        let mut buffer = vec![0xFFFF; 4 * 8];
+        assert_eq!(limiter(0.5, None).apply(&mut buffer), 1.0);
+        assert!(buffer.iter().all(|value| *value == 0x7FFF));
+    }
+
+    #[test]
+    fn estimate_per_univer() {
+        let mut buffer = vec![0; 4 * 8];
+        // Left univer full white, right univer off
+        for y in 0..2 {
+            for x in 0..2 {
+                let offset = (x + y * 4) * 4;
+                buffer[offset..offset + 4].copy_from_slice(&[0xFFFF; 4]);
+            }
+        }
+        // 4 pixels * 3 channels * 20 mA
+        assert_eq!(limiter(1.0, None).estimate(&buffer, 20.0), vec![240.0, 0.0]);
+    }
+
+    #[test]
+    fn matrix_budget() {
+        // 8 pixels * 3 channels * 20 mA = 480 mA
+        let mut buffer = vec![0xFFFF; 4 * 8];
+        let scale = limiter(1.0, Some(PowerBudget::Matrix(240.0))).apply(&mut buffer);
+        assert_eq!(scale, 0.5);
+        assert!(buffer.iter().all(|value| *value == 0x7FFF));
+        let mut buffer = vec![0xFFFF; 4 * 8];
+        let scale = limiter(0.5, Some(PowerBudget::Matrix(240.0))).apply(&mut buffer);
+        assert_eq!(scale, 1.0);
+    }
+
+    #[test]
+    fn per_univer_budget() {
+        let mut buffer = vec![0; 4 * 8];
+        buffer[0..4].copy_from_slice(&[0xFFFF; 4]);
+        buffer[4..8].copy_from_slice(&[0xFFFF; 4]);
+        // 2 pixels * 3 channels * 20 mA = 120 mA in the first univer only
+        let scale = limiter(1.0, Some(PowerBudget::PerUniver(60.0))).apply(&mut buffer);
+        assert_eq!(scale, 0.5);
+        assert_eq!(buffer[0], 0x7FFF);
+        let scale = limiter(1.0, Some(PowerBudget::PerUniver(200.0))).apply(&mut buffer);
+        assert_eq!(scale, 1.0);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(PowerOpt::default().validate().is_ok());
+        let opt = PowerOpt {
+            brightness: 1.5,
+            current_limit: None,
+        };
+        assert!(opt.validate().is_err());
+        let opt = PowerOpt {
+            brightness: 1.0,
+            current_limit: Some(CurrentLimit {
+                channel_current: 20.0,
+                budget: PowerBudget::Matrix(0.0),
+            }),
+        };
+        assert!(opt.validate().is_err());
+    }
+}
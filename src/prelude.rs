@@ -1,5 +1,13 @@
+pub use super::correction::*;
+pub use super::dithering::*;
 pub use super::encoder::*;
 pub use super::matrix::*;
+pub use super::node::*;
 pub use super::options::*;
+pub use super::pacing::*;
+pub use super::power::*;
 pub use super::screen::*;
+pub use super::sender::*;
+pub use super::transform::*;
+pub use super::transport::*;
 pub use super::GError;
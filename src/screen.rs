@@ -1,34 +1,229 @@
 use super::prelude::*;
 use fps_counter::FPSCounter;
+use std::time::{Duration, Instant};
+
+/// Informations about the frame sent to the encoder
+#[derive(Debug, Clone, Copy)]
+pub struct FrameInfo {
+    /// Actual frame rate
+    pub fps: usize,
+    /// Factor applied by the current limit (1.0 when the frame is under budget)
+    pub power_scale: f32,
+}
 
 pub struct Screen<T: Encoder + Sized> {
     matrix: AddrMap,
-    buffer: Vec<u8>,
+    buffer: Vec<u16>,
+    /// `buffer` once color corrected
+    corrected: Vec<u16>,
+    correction: ColorCorrection,
+    power: PowerLimiter,
+    /// Only used with 8 bits color depth
+    dithering: Option<TemporalDithering>,
     output_encoder: T,
     fps: FPSCounter,
+    pacer: FramePacer,
+    /// Background output thread, frames are pushed to it instead of being encoded in place
+    sender: Option<OutputSender>,
 }
 
 impl<T: Encoder + Sized> Screen<T> {
     pub fn new(matrix: AddrMap, output_encoder: T) -> Self {
         let width = matrix.opt.width;
         let height = matrix.opt.height;
-        Self {
-            buffer: vec![0; width * height * matrix.opt.pixel_size],
+        let buffer = vec![0; width * height * matrix.opt.input_pixel_size];
+        let dithering = matrix.opt.dithering;
+        let mut screen = Self {
+            corrected: buffer.clone(),
+            buffer,
+            correction: ColorCorrection::new(&matrix.opt.correction),
+            power: PowerLimiter::new(matrix.opt.power.clone(), &matrix),
+            dithering: None,
+            pacer: FramePacer::new(&matrix.opt.pacing),
+            sender: None,
             matrix,
             output_encoder,
             fps: FPSCounter::new(),
+        };
+        screen.set_dithering(dithering);
+        screen
+    }
+
+    /// Apply a screen buffer (8 bits RGBA) and output it to the encoder
+    /// buffer size must match matrix size, return actual FPS and applied power scale
+    pub fn apply<'a>(&'a mut self, buffer: &[u8]) -> (FrameInfo, &'a [UniverBuffer]) {
+        self.load(buffer);
+        self.output()
+    }
+
+    /// Same as `apply` with a 16 bits RGBA buffer
+    pub fn apply_16<'a>(&'a mut self, buffer: &[u16]) -> (FrameInfo, &'a [UniverBuffer]) {
+        assert_eq!(buffer.len(), self.buffer.len());
+        self.buffer.copy_from_slice(buffer);
+        self.output()
+    }
+
+    /// Blocking version of `apply` paced at the configured refresh rate: the 8 bits RGBA
+    /// `buffer` is displayed during `duration`, `output` is called at each refresh tick
+    /// (several times when the frame is repeated, never when it is dropped).
+    /// Return the info of the last output frame, stop on the first error of `output`
+    pub fn present<F>(
+        &mut self,
+        buffer: &[u8],
+        duration: Duration,
+        mut output: F,
+    ) -> Result<Option<FrameInfo>, GError>
+    where
+        F: FnMut(&[UniverBuffer]) -> Result<(), GError>,
+    {
+        self.load(buffer);
+        let mut info = None;
+        for deadline in self.pacer.schedule(duration, Instant::now()) {
+            wait_until(deadline);
+            self.pacer.record(deadline, Instant::now());
+            let (frame_info, packets) = self.output();
+            output(packets)?;
+            info = Some(frame_info);
         }
+        Ok(info)
+    }
+
+    /// Start the background output thread, frames given to `push` are encoded and passed to
+    /// `sink` at the configured refresh rate, the last frame is re-sent as a keep-alive.
+    /// The thread uses its own copy of the encoder and of the configuration (restart it to
+    /// apply a new refresh rate)
+    pub fn start_output<F>(&mut self, sink: F)
+    where
+        T: Clone + Send + 'static,
+        F: FnMut(&[UniverBuffer]) -> Result<(), GError> + Send + 'static,
+    {
+        // Stop the previous thread before spawning the new one
+        self.sender = None;
+        self.sender = Some(OutputSender::spawn(
+            self.matrix.clone(),
+            self.output_encoder.clone(),
+            sink,
+        ));
+    }
+
+    /// Same as `start_output` with a sink given the last frame once more when the output
+    /// stops, ex: a `Router` sends the sACN stream termination packets on `stop_output` or
+    /// when the screen is dropped
+    pub fn start_output_sink<S>(&mut self, sink: S)
+    where
+        T: Clone + Send + 'static,
+        S: OutputSink + Send + 'static,
+    {
+        self.sender = None;
+        self.sender = Some(OutputSender::spawn_sink(
+            self.matrix.clone(),
+            self.output_encoder.clone(),
+            sink,
+        ));
+    }
+
+    /// Stop the background output thread
+    pub fn stop_output(&mut self) {
+        self.sender = None;
+    }
+
+    /// Process a 8 bits RGBA buffer and queue it to the output thread,
+    /// block while the thread is late by more than two frames
+    pub fn push(&mut self, buffer: &[u8]) -> Result<FrameInfo, GError> {
+        self.load(buffer);
+        self.queue()
+    }
+
+    /// Same as `push` with a 16 bits RGBA buffer
+    pub fn push_16(&mut self, buffer: &[u16]) -> Result<FrameInfo, GError> {
+        assert_eq!(buffer.len(), self.buffer.len());
+        self.buffer.copy_from_slice(buffer);
+        self.queue()
     }
 
-    /// Apply a screen buffer and output it to the encoder
-    /// buffer size must match matrix size, return actual FPS
-    pub fn apply<'a>(&'a mut self, buffer: &[u8]) -> (usize, &'a [super::dmx::ArtDmx]) {
+    /// Frame pacing statistics of `present`
+    pub fn pacing_stats(&self) -> &PacingStats {
+        self.pacer.stats()
+    }
+
+    /// Replace the refresh rate given by the matrix configuration
+    pub fn set_pacing(&mut self, opt: PacingOpt) -> Result<(), GError> {
+        opt.validate()?;
+        self.pacer = FramePacer::new(&opt);
+        self.matrix.opt.pacing = opt;
+        Ok(())
+    }
+
+    /// Replace the color correction given by the matrix configuration
+    pub fn set_correction(&mut self, opt: CorrectionOpt) -> Result<(), GError> {
+        opt.validate()?;
+        self.correction = ColorCorrection::new(&opt);
+        self.matrix.opt.correction = opt;
+        Ok(())
+    }
+
+    /// Replace the brightness and current limit given by the matrix configuration
+    pub fn set_power(&mut self, opt: PowerOpt) -> Result<(), GError> {
+        opt.validate()?;
+        self.power = PowerLimiter::new(opt.clone(), &self.matrix);
+        self.matrix.opt.power = opt;
+        Ok(())
+    }
+
+    /// Change the master brightness (between 0.0 and 1.0)
+    pub fn set_brightness(&mut self, brightness: f32) -> Result<(), GError> {
+        let opt = PowerOpt {
+            brightness,
+            ..self.power.opt().clone()
+        };
+        self.set_power(opt)
+    }
+
+    /// Enable or disable temporal dithering (ignored with 16 bits color depth)
+    pub fn set_dithering(&mut self, enabled: bool) {
+        self.matrix.opt.dithering = enabled;
+        self.dithering = if enabled && self.matrix.opt.color_depth == ColorDepth::Bit8 {
+            Some(TemporalDithering::new(self.buffer.len()))
+        } else {
+            None
+        };
+    }
+
+    fn load(&mut self, buffer: &[u8]) {
         assert_eq!(buffer.len(), self.buffer.len());
-        unsafe { self.buffer.set_len(0) };
-        self.buffer.extend_from_slice(buffer);
+        self.buffer
+            .iter_mut()
+            .zip(buffer.iter())
+            .for_each(|(dst, src)| *dst = upscale(*src));
+    }
+
+    /// Correct `buffer` into `corrected`
+    fn process(&mut self) -> FrameInfo {
+        self.correction.apply(&self.buffer, &mut self.corrected);
+        let power_scale = self.power.apply(&mut self.corrected);
+        if let Some(dithering) = self.dithering.as_mut() {
+            dithering.apply(&mut self.corrected);
+        }
+        FrameInfo {
+            fps: self.fps.tick(),
+            power_scale,
+        }
+    }
+
+    fn output(&mut self) -> (FrameInfo, &[UniverBuffer]) {
+        let info = self.process();
         (
-            self.fps.tick(),
-            self.output_encoder.encode(&self.matrix, &self.buffer),
+            info,
+            self.output_encoder.encode(&self.matrix, &self.corrected),
         )
     }
+
+    fn queue(&mut self) -> Result<FrameInfo, GError> {
+        let info = self.process();
+        self.sender
+            .as_ref()
+            .ok_or(GError::OutputStopped)?
+            .push(self.corrected.clone())?;
+        Ok(info)
+    }
 }
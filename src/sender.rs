@@ -0,0 +1,274 @@
+//!
+//! Background output thread: frames are encoded and transmitted at the configured refresh rate
+//! so that a slow source never stalls the leds, the last frame is re-sent as a keep-alive
+//!
+
+use crate::prelude::*;
+use std::sync::mpsc::{sync_channel, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Frames waiting for the output thread, with the frame being sent this makes a triple buffer
+const QUEUE_SIZE: usize = 2;
+
+/// Destination of the frames of the output thread
+pub trait OutputSink {
+    /// Send the univers of a frame
+    fn send(&mut self, univers: &[UniverBuffer]) -> Result<(), GError>;
+
+    /// Called with the last frame when the output thread stops
+    fn stop(&mut self, _univers: &[UniverBuffer]) -> Result<(), GError> {
+        Ok(())
+    }
+}
+
+/// Sink calling a closure with each frame
+struct FnSink<F>(F);
+
+impl<F: FnMut(&[UniverBuffer]) -> Result<(), GError>> OutputSink for FnSink<F> {
+    fn send(&mut self, univers: &[UniverBuffer]) -> Result<(), GError> {
+        (self.0)(univers)
+    }
+}
+
+/// A router ends the streams of its univers (sACN receivers release the univers at once
+/// instead of holding the last frame until their timeout)
+impl<S: Serializer, T: Transport> OutputSink for Router<S, T> {
+    fn send(&mut self, univers: &[UniverBuffer]) -> Result<(), GError> {
+        Router::send(self, univers)
+    }
+
+    fn stop(&mut self, univers: &[UniverBuffer]) -> Result<(), GError> {
+        self.terminate(univers)
+    }
+}
+
+/// Handle of the output thread, the thread stops once the handle is dropped
+pub struct OutputSender {
+    queue: Option<SyncSender<Vec<u16>>>,
+    thread: Option<JoinHandle<()>>,
+    /// Last error returned by the sink, reported by the next `push`
+    error: Arc<Mutex<Option<GError>>>,
+}
+
+impl OutputSender {
+    /// Spawn the output thread, `sink` is called with the encoded packets of each transmitted frame
+    /// (ex: `move |univers| router.send(univers)`)
+    pub fn spawn<T, F>(matrix: AddrMap, encoder: T, sink: F) -> Self
+    where
+        T: Encoder + Send + 'static,
+        F: FnMut(&[UniverBuffer]) -> Result<(), GError> + Send + 'static,
+    {
+        Self::spawn_sink(matrix, encoder, FnSink(sink))
+    }
+
+    /// Same as `spawn` with a sink also given the last frame once the thread stops
+    /// (ex: a `Router` ends its sACN streams)
+    pub fn spawn_sink<T, S>(matrix: AddrMap, mut encoder: T, mut sink: S) -> Self
+    where
+        T: Encoder + Send + 'static,
+        S: OutputSink + Send + 'static,
+    {
+        let error = Arc::new(Mutex::new(None));
+        let thread_error = error.clone();
+        let (queue, frames) = sync_channel::<Vec<u16>>(QUEUE_SIZE);
+        let interval = matrix.opt.pacing.interval();
+        let keep_alive = Duration::from_millis(matrix.opt.pacing.keep_alive);
+        let thread = std::thread::spawn(move || {
+            let mut last: Option<Vec<u16>> = None;
+            let mut last_sent = Instant::now();
+            let mut next_tick = Instant::now();
+            loop {
+                wait_until(next_tick);
+                next_tick = std::cmp::max(next_tick + interval, Instant::now());
+                // Only the newest frame is sent, older ones are dropped
+                let mut fresh = false;
+                loop {
+                    match frames.try_recv() {
+                        Ok(frame) => {
+                            last = Some(frame);
+                            fresh = true;
+                        }
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => {
+                            // Nobody is left to report an error to
+                            if let Some(frame) = &last {
+                                let _ = sink.stop(encoder.encode(&matrix, frame));
+                            }
+                            return;
+                        }
+                    }
+                }
+                if let Some(frame) = &last {
+                    if fresh || last_sent.elapsed() >= keep_alive {
+                        if let Err(e) = sink.send(encoder.encode(&matrix, frame)) {
+                            *thread_error.lock().unwrap() = Some(e);
+                        }
+                        last_sent = Instant::now();
+                    }
+                }
+            }
+        });
+        Self {
+            queue: Some(queue),
+            thread: Some(thread),
+            error,
+        }
+    }
+
+    /// Queue a 16 bits RGBA frame (already corrected), block while the queue is full.
+    /// Fail with the last error of the sink if any
+    pub fn push(&self, frame: Vec<u16>) -> Result<(), GError> {
+        if let Some(e) = self.error.lock().unwrap().take() {
+            return Err(e);
+        }
+        self.queue
+            .as_ref()
+            .ok_or(GError::OutputStopped)?
+            .send(frame)
+            .map_err(|_| GError::OutputStopped)
+    }
+}
+
+impl Drop for OutputSender {
+    fn drop(&mut self) {
+        // Disconnect the queue so that the thread exits on its next tick
+        self.queue.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::sync::mpsc::channel;
+
+    /// 2x2 RGB matrix in a single univer
+    fn matrix(keep_alive: u64) -> AddrMap {
+        matrix_with(keep_alive, Protocol::Artnet)
+    }
+
+    fn matrix_with(keep_alive: u64, protocol: Protocol) -> AddrMap {
+        AddrMap::from_mapping(
+            MappingOptExt::try_from(MappingOpt {
+                dmx_size: 12,
+                width: 2,
+                height: 2,
+                univer_height: 2,
+                color_mode: ColorMode::RGB,
+                displacement: Displacement::Progressive,
+                direction: Direction::Vertical,
+                orientation: vec![Orientation::TopLeft],
+                start_address: 0,
+                port_addresses: None,
+                channels: None,
+                color_depth: ColorDepth::Bit8,
+                correction: CorrectionOpt::default(),
+                power: PowerOpt::default(),
+                dithering: false,
+                pacing: PacingOpt {
+                    refresh_rate: 200.0,
+                    max_rate: 200.0,
+                    keep_alive,
+                },
+                nodes: None,
+                protocol,
+                artnet: ArtnetOpt::default(),
+            })
+            .unwrap(),
+        )
+    }
+
+    fn sender(keep_alive: u64) -> (OutputSender, std::sync::mpsc::Receiver<Vec<u8>>) {
+        let matrix = matrix(keep_alive);
+        let encoder = ArtnetEncoder::new(matrix.opt.clone());
+        let (tx, rx) = channel();
+        let sender = OutputSender::spawn(matrix, encoder, move |univers| {
+            let _ = tx.send(univers[0].slots().to_vec());
+            Ok(())
+        });
+        (sender, rx)
+    }
+
+    #[test]
+    fn frames_are_sent() {
+        let (sender, rx) = sender(10_000);
+        sender.push(vec![upscale(42); 16]).unwrap();
+        let data = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(data, vec![42; 12]);
+    }
+
+    #[test]
+    fn last_frame_is_kept_alive() {
+        let (sender, rx) = sender(20);
+        sender.push(vec![upscale(7); 16]).unwrap();
+        for _ in 0..3 {
+            let data = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+            assert_eq!(data, vec![7; 12]);
+        }
+    }
+
+    #[test]
+    fn sink_errors_are_reported() {
+        let matrix = matrix(10_000);
+        let encoder = ArtnetEncoder::new(matrix.opt.clone());
+        let (tx, rx) = channel();
+        let sender = OutputSender::spawn(matrix, encoder, move |_| {
+            let _ = tx.send(());
+            Err(GError::OutputStopped)
+        });
+        sender.push(vec![0; 16]).unwrap();
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        // The error is recorded once the sink returns, every accepted frame calls the sink again
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while sender.push(vec![0; 16]).is_ok() {
+            assert!(Instant::now() < deadline, "the sink error is not reported");
+            rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        }
+    }
+
+    #[test]
+    fn sacn_streams_are_terminated_on_drop() {
+        let e131 = E131Opt::default();
+        let matrix = matrix_with(10_000, Protocol::E131(e131.clone()));
+        let encoder = ArtnetEncoder::new(matrix.opt.clone());
+        let transport = LoopbackTransport::new();
+        let serializer = E131Serializer::new(&e131).unwrap();
+        let router = Router::from_mapping(&matrix.opt, serializer, transport.clone());
+        let sender = OutputSender::spawn_sink(matrix, encoder, router);
+        sender.push(vec![upscale(42); 16]).unwrap();
+        let mut packets = vec![];
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while packets.is_empty() {
+            assert!(Instant::now() < deadline, "no frame was sent");
+            std::thread::sleep(Duration::from_millis(5));
+            packets = transport.take();
+        }
+        drop(sender);
+        packets.extend(transport.take());
+        let packets: Vec<E131Data> = packets
+            .iter()
+            .map(|(_, packet)| E131Data::parse(packet).unwrap())
+            .collect();
+        let (frames, terminated) = packets.split_at(packets.len() - 3);
+        assert!(frames.iter().all(|data| !data.stream_terminated));
+        let last = frames.last().unwrap().sequence;
+        for (i, data) in terminated.iter().enumerate() {
+            assert!(data.stream_terminated);
+            assert_eq!(data.sequence, last.wrapping_add(i as u8 + 1));
+            assert_eq!(data.slots, vec![42; 12]);
+        }
+    }
+
+    #[test]
+    fn thread_stops_on_drop() {
+        let (sender, rx) = sender(20);
+        drop(sender);
+        // The sink (and its channel) is dropped with the thread
+        assert!(rx.recv_timeout(Duration::from_secs(1)).is_err());
+    }
+}
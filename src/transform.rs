@@ -0,0 +1,186 @@
+//!
+//! Per pixel channel transformation applied between the input buffer and the DMX slots
+//!
+
+use crate::prelude::*;
+use std::cmp::min;
+
+use Channel::*;
+
+impl ColorMode {
+    /// Native channels of the fixture (in DMX order)
+    pub fn channels(self) -> &'static [Channel] {
+        match self {
+            ColorMode::RGBA => &[Red, Green, Blue, Alpha],
+            ColorMode::RGB => &[Red, Green, Blue],
+            ColorMode::GRB => &[Green, Red, Blue],
+            ColorMode::BRG => &[Blue, Red, Green],
+            ColorMode::RGBW => &[Red, Green, Blue, White],
+            ColorMode::GRBW => &[Green, Red, Blue, White],
+            ColorMode::WRGB => &[White, Red, Green, Blue],
+            ColorMode::W => &[White],
+            ColorMode::Dimmer => &[Luminance],
+        }
+    }
+
+    /// Number of DMX channels used by one pixel
+    pub fn pixel_size(self) -> usize {
+        self.channels().len()
+    }
+}
+
+impl ColorDepth {
+    /// Number of DMX slots used by one channel
+    pub fn slots(self) -> usize {
+        match self {
+            ColorDepth::Bit8 => 1,
+            ColorDepth::Bit16 => 2,
+        }
+    }
+}
+
+/// Convert an 8 bits value into its 16 bits equivalent (0xFF -> 0xFFFF)
+pub fn upscale(value: u8) -> u16 {
+    u16::from(value) * 257
+}
+
+impl Channel {
+    /// Compute the channel value from a 16 bits RGBA input pixel
+    pub fn value(self, pixel: &[u16]) -> u16 {
+        match self {
+            Red => pixel[0],
+            Green => pixel[1],
+            Blue => pixel[2],
+            Alpha => pixel[3],
+            White => min(pixel[0], min(pixel[1], pixel[2])),
+            // Rec. 709 coefficients scaled to 256
+            Luminance => {
+                ((54 * u32::from(pixel[0]) + 183 * u32::from(pixel[1]) + 19 * u32::from(pixel[2]))
+                    >> 8) as u16
+            }
+            Zero => 0,
+        }
+    }
+}
+
+/// Convert input pixels into fixture pixels following `MappingOptExt::channels`
+#[derive(Debug, Clone)]
+pub struct ChannelTransform {
+    channels: Vec<Channel>,
+    color_depth: ColorDepth,
+}
+
+impl ChannelTransform {
+    pub fn new(opt: &MappingOptExt) -> Self {
+        Self {
+            channels: opt.channels.clone(),
+            color_depth: opt.color_depth,
+        }
+    }
+
+    /// Write the fixture channels of one input pixel into `output`, 16 bits channels are
+    /// written coarse byte first. `output` must be `MappingOptExt::pixel_size` long
+    pub fn apply(&self, input: &[u16], output: &mut [u8]) {
+        let slots = output.chunks_mut(self.color_depth.slots());
+        for (slot, channel) in slots.zip(self.channels.iter()) {
+            let value = channel.value(input);
+            match self.color_depth {
+                ColorDepth::Bit8 => slot[0] = (value >> 8) as u8,
+                ColorDepth::Bit16 => {
+                    slot[0] = (value >> 8) as u8;
+                    slot[1] = value as u8;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(channels: Vec<Channel>, input: &[u8]) -> Vec<u8> {
+        let mut output = vec![0xFF; channels.len()];
+        let input: Vec<u16> = input.iter().cloned().map(upscale).collect();
+        let color_depth = ColorDepth::Bit8;
+        ChannelTransform {
+            channels,
+            color_depth,
+        }
+        .apply(&input, &mut output);
+        output
+    }
+
+    #[test]
+    fn reorder_channels() {
+        let channels = vec![Green, Red, Blue];
+        assert_eq!(transform(channels, &[1, 2, 3, 4]), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn drop_alpha() {
+        let channels = ColorMode::RGB.channels().to_vec();
+        assert_eq!(transform(channels, &[1, 2, 3, 4]), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn color_mode_native_order() {
+        let input = [10, 20, 30, 40];
+        let expected: [(ColorMode, &[u8]); 9] = [
+            (ColorMode::RGBA, &[10, 20, 30, 40]),
+            (ColorMode::RGB, &[10, 20, 30]),
+            (ColorMode::GRB, &[20, 10, 30]),
+            (ColorMode::BRG, &[30, 10, 20]),
+            (ColorMode::RGBW, &[10, 20, 30, 10]),
+            (ColorMode::GRBW, &[20, 10, 30, 10]),
+            (ColorMode::WRGB, &[10, 10, 20, 30]),
+            (ColorMode::W, &[10]),
+            (ColorMode::Dimmer, &[18]),
+        ];
+        for (color_mode, output) in expected.iter() {
+            assert_eq!(color_mode.pixel_size(), output.len());
+            let channels = color_mode.channels().to_vec();
+            assert_eq!(
+                &transform(channels, &input)[..],
+                *output,
+                "{:?}",
+                color_mode
+            );
+        }
+    }
+
+    #[test]
+    fn luminance_of_white_is_full() {
+        assert_eq!(transform(vec![Luminance], &[255, 255, 255, 0]), vec![255]);
+    }
+
+    #[test]
+    fn coarse_fine_channels() {
+        let mut output = vec![0; 4];
+        ChannelTransform {
+            channels: vec![Red, Blue],
+            color_depth: ColorDepth::Bit16,
+        }
+        .apply(&[0x1234, 0, 0xABCD, 0], &mut output);
+        assert_eq!(output, vec![0x12, 0x34, 0xAB, 0xCD]);
+    }
+
+    #[test]
+    fn upscale_full_range() {
+        assert_eq!(upscale(0), 0);
+        assert_eq!(upscale(0x80), 0x8080);
+        assert_eq!(upscale(0xFF), 0xFFFF);
+    }
+
+    #[test]
+    fn derive_white() {
+        let channels = vec![Red, Green, Blue, White];
+        assert_eq!(transform(channels, &[200, 50, 120]), vec![200, 50, 120, 50]);
+    }
+
+    #[test]
+    fn zero_channel() {
+        let channels = vec![Zero, Alpha];
+        assert_eq!(transform(channels, &[1, 2, 3, 4]), vec![0, 4]);
+    }
+}
@@ -0,0 +1,497 @@
+//!
+//! Packets transmission: a `Transport` sends datagrams and a `Router` sends each univer
+//! to the node it is wired to
+//!
+
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Art-Net UDP port
+pub const ARTNET_PORT: u16 = 6454;
+
+/// Send datagrams to a network address
+pub trait Transport {
+    fn send_to(&mut self, packet: &[u8], addr: &SocketAddr) -> Result<(), GError>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send_to(&mut self, packet: &[u8], addr: &SocketAddr) -> Result<(), GError> {
+        (**self).send_to(packet, addr)
+    }
+}
+
+/// UDP transport, broadcast addresses are allowed
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, GError> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_broadcast(true)?;
+        Ok(Self { socket })
+    }
+
+    /// Underlying socket, used to receive node replies
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to(&mut self, packet: &[u8], addr: &SocketAddr) -> Result<(), GError> {
+        self.socket.send_to(packet, addr)?;
+        Ok(())
+    }
+}
+
+/// TCP transport, a connection is opened to each node on its first packet and
+/// reopened on the next packet after an error
+#[derive(Debug, Default)]
+pub struct TcpTransport {
+    streams: HashMap<SocketAddr, TcpStream>,
+}
+
+impl TcpTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Connection to `addr`, opened when needed
+fn connect<'a>(
+    streams: &'a mut HashMap<SocketAddr, TcpStream>,
+    addr: &SocketAddr,
+) -> Result<&'a mut TcpStream, GError> {
+    if !streams.contains_key(addr) {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        streams.insert(*addr, stream);
+    }
+    Ok(streams.get_mut(addr).unwrap())
+}
+
+impl Transport for TcpTransport {
+    fn send_to(&mut self, packet: &[u8], addr: &SocketAddr) -> Result<(), GError> {
+        let result = connect(&mut self.streams, addr)?.write_all(packet);
+        if result.is_err() {
+            self.streams.remove(addr);
+        }
+        Ok(result?)
+    }
+}
+
+/// OLA daemon RPC transport, sends a request and waits for its response
+#[derive(Debug, Default)]
+pub struct OlaTransport {
+    streams: HashMap<SocketAddr, TcpStream>,
+}
+
+impl OlaTransport {
+    /// Maximum time waited for a response
+    pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn request(stream: &mut TcpStream, packet: &[u8]) -> Result<(), GError> {
+        let request = RpcMessage::read(&mut &*packet)?;
+        stream.set_read_timeout(Some(Self::RESPONSE_TIMEOUT))?;
+        stream.write_all(packet)?;
+        let response = RpcMessage::read(stream)?;
+        // A response to an older request would acknowledge the wrong univer
+        if response.id != request.id {
+            return Err(GError::InvalidPacket(
+                "OLA response id doesn't match the request",
+            ));
+        }
+        match response.kind {
+            RpcType::Response => Ok(()),
+            RpcType::ResponseFailed => Err(GError::OlaRequest(
+                String::from_utf8_lossy(&response.buffer).into_owned(),
+            )),
+            kind => Err(GError::OlaRequest(format!("unexpected {:?} message", kind))),
+        }
+    }
+}
+
+impl Transport for OlaTransport {
+    fn send_to(&mut self, packet: &[u8], addr: &SocketAddr) -> Result<(), GError> {
+        let result = Self::request(connect(&mut self.streams, addr)?, packet);
+        // The stream may hold a late response, the next request uses a new connection
+        if let Err(GError::Io(_)) | Err(GError::InvalidPacket(_)) = result {
+            self.streams.remove(addr);
+        }
+        result
+    }
+}
+
+/// Serial transport of an Enttec DMX USB Pro widget, node addresses are ignored
+#[derive(Debug)]
+pub struct SerialTransport {
+    device: File,
+}
+
+impl SerialTransport {
+    /// Open the widget device in raw mode and program its break timing
+    pub fn open(opt: &EnttecOpt) -> Result<Self, GError> {
+        opt.validate()?;
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&opt.device)?;
+        // Without raw mode the tty driver would translate some bytes of the messages
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(device.as_raw_fd(), &mut termios) != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(device.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        let mut transport = Self { device };
+        transport.device.write_all(&enttec_parameters(opt))?;
+        Ok(transport)
+    }
+
+    /// Underlying device, used to read the widget replies
+    pub fn device(&self) -> &File {
+        &self.device
+    }
+}
+
+impl Transport for SerialTransport {
+    fn send_to(&mut self, packet: &[u8], _addr: &SocketAddr) -> Result<(), GError> {
+        self.device.write_all(packet)?;
+        Ok(())
+    }
+}
+
+/// Datagrams and their destination
+pub type Datagrams = Vec<(SocketAddr, Vec<u8>)>;
+
+/// In memory transport keeping every sent datagram, clones share the same datagrams
+#[derive(Debug, Clone, Default)]
+pub struct LoopbackTransport {
+    packets: Arc<Mutex<Datagrams>>,
+}
+
+impl LoopbackTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove and return the datagrams sent so far
+    pub fn take(&self) -> Datagrams {
+        std::mem::take(&mut *self.packets.lock().unwrap())
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send_to(&mut self, packet: &[u8], addr: &SocketAddr) -> Result<(), GError> {
+        self.packets.lock().unwrap().push((*addr, packet.to_vec()));
+        Ok(())
+    }
+}
+
+/// Serialize each univer and send it to its node
+#[derive(Debug)]
+pub struct Router<S: Serializer, T: Transport> {
+    serializer: S,
+    transport: T,
+    /// Node address of each univer
+    nodes: Vec<SocketAddr>,
+}
+
+impl<S: Serializer, T: Transport> Router<S, T> {
+    pub fn new(serializer: S, transport: T, nodes: Vec<SocketAddr>) -> Self {
+        Self {
+            serializer,
+            transport,
+            nodes,
+        }
+    }
+
+    /// Route univers to the nodes given by the matrix configuration
+    pub fn from_mapping(opt: &MappingOptExt, serializer: S, transport: T) -> Self {
+        Self::new(serializer, transport, opt.nodes.clone())
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn serializer_mut(&mut self) -> &mut S {
+        &mut self.serializer
+    }
+
+    /// Send the univers of a frame, stop on the first error
+    pub fn send(&mut self, univers: &[UniverBuffer]) -> Result<(), GError> {
+        let transport = &mut self.transport;
+        let mut nodes: Vec<SocketAddr> = vec![];
+        self.serializer.begin_frame(&self.nodes);
+        for (univer, node) in univers.iter().zip(self.nodes.iter()) {
+            self.serializer
+                .serialize(univer, &mut |packet| transport.send_to(packet, node))?;
+            if !nodes.contains(node) {
+                nodes.push(*node);
+            }
+        }
+        self.serializer
+            .end_frame(&nodes, &mut |packet, node| transport.send_to(packet, node))
+    }
+
+    /// Send the last packets of the univers of the last frame (ex: sACN stream termination)
+    pub fn terminate(&mut self, univers: &[UniverBuffer]) -> Result<(), GError> {
+        let transport = &mut self.transport;
+        for (univer, node) in univers.iter().zip(self.nodes.iter()) {
+            self.serializer
+                .terminate(univer, &mut |packet| transport.send_to(packet, node))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use artnet_protocol::ArtCommand;
+    use std::convert::TryFrom;
+
+    fn opt(nodes: Option<Vec<SocketAddr>>) -> MappingOptExt {
+        MappingOptExt::try_from(mapping(4, nodes, Protocol::Artnet)).unwrap()
+    }
+
+    fn mapping(width: usize, nodes: Option<Vec<SocketAddr>>, protocol: Protocol) -> MappingOpt {
+        MappingOpt {
+            dmx_size: 12,
+            width,
+            height: 2,
+            univer_height: 2,
+            color_mode: ColorMode::RGB,
+            displacement: Displacement::Progressive,
+            direction: Direction::Vertical,
+            orientation: vec![Orientation::TopLeft; 2],
+            start_address: 0x10,
+            port_addresses: None,
+            channels: None,
+            color_depth: ColorDepth::Bit8,
+            correction: CorrectionOpt::default(),
+            power: PowerOpt::default(),
+            dithering: false,
+            pacing: PacingOpt::default(),
+            nodes,
+            protocol,
+            artnet: ArtnetOpt::default(),
+        }
+    }
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    fn route(opt: MappingOptExt) -> Datagrams {
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        let transport = LoopbackTransport::new();
+        let mut router = Router::from_mapping(&opt, ArtnetSerializer::default(), transport.clone());
+        router
+            .send(encoder.encode(&matrix, &[0xFFFF; 4 * 8]))
+            .unwrap();
+        transport.take()
+    }
+
+    #[test]
+    fn univers_are_broadcast_by_default() {
+        let packets = route(opt(None));
+        assert_eq!(packets.len(), 2);
+        for (node, _) in packets.iter() {
+            assert_eq!(*node, addr("255.255.255.255:6454"));
+        }
+    }
+
+    #[test]
+    fn univers_are_routed_to_their_node() {
+        let nodes = vec![addr("10.0.0.1:6454"), addr("10.0.0.2:6454")];
+        let packets = route(opt(Some(nodes.clone())));
+        assert_eq!(packets[0].0, nodes[0]);
+        assert_eq!(packets[1].0, nodes[1]);
+        match ArtCommand::from_buffer(&packets[1].1).unwrap() {
+            ArtCommand::Output(output) => {
+                assert_eq!(u16::from(output.port_address), 0x11);
+                assert_eq!(output.data, vec![0xFF; 12]);
+            }
+            _ => panic!("expected an ArtDmx packet"),
+        }
+    }
+
+    #[test]
+    fn udp_transport_sends_datagrams() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut transport = UdpTransport::bind("127.0.0.1:0").unwrap();
+        transport
+            .send_to(&[1, 2, 3], &receiver.local_addr().unwrap())
+            .unwrap();
+        let mut buffer = [0; 16];
+        let (len, from) = receiver.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[0..len], &[1, 2, 3]);
+        assert_eq!(from, transport.socket().local_addr().unwrap());
+    }
+
+    /// Mock olad answering each `UpdateDmxData` request with `reply`, the received univers
+    /// are returned when the client disconnects
+    fn mock_olad(
+        reply: fn(&DmxData) -> RpcMessage,
+    ) -> (SocketAddr, std::thread::JoinHandle<Vec<DmxData>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut univers = vec![];
+            while let Ok(request) = RpcMessage::read(&mut stream) {
+                assert_eq!(request.kind, RpcType::Request);
+                assert_eq!(request.name, OLA_UPDATE_DMX_DATA);
+                let dmx = DmxData::decode(&request.buffer).unwrap();
+                RpcMessage {
+                    id: request.id,
+                    ..reply(&dmx)
+                }
+                .write(&mut stream)
+                .unwrap();
+                univers.push(dmx);
+            }
+            univers
+        });
+        (addr, server)
+    }
+
+    fn ola_route(addr: SocketAddr) -> Result<(), GError> {
+        let opt = opt(Some(vec![addr; 2]));
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        let mut router = Router::from_mapping(&opt, OlaSerializer::new(), OlaTransport::new());
+        router.send(encoder.encode(&matrix, &[0xFFFF; 4 * 8]))
+    }
+
+    #[test]
+    fn ola_transport_sends_update_dmx_data() {
+        let (addr, server) = mock_olad(|_| RpcMessage {
+            kind: RpcType::Response,
+            id: 0,
+            name: String::new(),
+            buffer: vec![],
+        });
+        ola_route(addr).unwrap();
+        let univers = server.join().unwrap();
+        assert_eq!(univers.len(), 2);
+        assert_eq!(univers[1].universe, 0x11);
+        assert_eq!(univers[1].data, vec![0xFF; 12]);
+    }
+
+    #[test]
+    fn ola_transport_reports_failed_requests() {
+        let (addr, server) = mock_olad(|dmx| RpcMessage {
+            kind: match dmx.universe {
+                0x10 => RpcType::Response,
+                _ => RpcType::ResponseFailed,
+            },
+            id: 0,
+            name: String::new(),
+            buffer: b"Universe doesn't exist".to_vec(),
+        });
+        match ola_route(addr) {
+            Err(GError::OlaRequest(error)) => assert_eq!(error, "Universe doesn't exist"),
+            result => panic!("expected a failed request, got {:?}", result),
+        }
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn ola_transport_rejects_mismatched_responses() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = RpcMessage::read(&mut stream).unwrap();
+            RpcMessage {
+                kind: RpcType::Response,
+                id: request.id.wrapping_add(1),
+                name: String::new(),
+                buffer: vec![],
+            }
+            .write(&mut stream)
+            .unwrap();
+        });
+        match ola_route(addr) {
+            Err(GError::InvalidPacket(_)) => {}
+            result => panic!("expected a mismatched response, got {:?}", result),
+        }
+        server.join().unwrap();
+    }
+
+    /// Master side and device path of a pseudo-terminal pair
+    fn pty() -> (File, File, std::path::PathBuf) {
+        use std::os::unix::io::FromRawFd;
+        let (mut master, mut slave) = (0, 0);
+        unsafe {
+            assert_eq!(
+                libc::openpty(
+                    &mut master,
+                    &mut slave,
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                ),
+                0
+            );
+            let path = std::ffi::CStr::from_ptr(libc::ttyname(slave))
+                .to_str()
+                .unwrap()
+                .into();
+            (File::from_raw_fd(master), File::from_raw_fd(slave), path)
+        }
+    }
+
+    #[test]
+    fn serial_transport_sends_enttec_messages() {
+        let (mut master, _slave, device) = pty();
+        let enttec = EnttecOpt {
+            device,
+            ..EnttecOpt::default()
+        };
+        let opt =
+            MappingOptExt::try_from(mapping(2, None, Protocol::Enttec(enttec.clone()))).unwrap();
+        assert!(
+            MappingOptExt::try_from(mapping(4, None, Protocol::Enttec(enttec.clone()))).is_err()
+        );
+        let matrix = AddrMap::from_mapping(opt.clone());
+        let mut encoder = ArtnetEncoder::new(opt.clone());
+        let transport = SerialTransport::open(&enttec).unwrap();
+        let mut router = Router::from_mapping(&opt, EnttecSerializer::new(), transport);
+        let parameters = EnttecMessage::read(&mut master).unwrap();
+        assert_eq!(parameters.label, ENTTEC_SET_PARAMETERS);
+        assert_eq!(parameters.data, vec![0, 0, 9, 1, 0]);
+        // 0x0A would be translated into 0x0D 0x0A outside of raw mode
+        router
+            .send(encoder.encode(&matrix, &[upscale(0x0A); 4 * 4]))
+            .unwrap();
+        let dmx = EnttecMessage::read(&mut master).unwrap();
+        assert_eq!(dmx.label, ENTTEC_SEND_DMX);
+        assert_eq!(dmx.data[0], 0);
+        assert_eq!(&dmx.data[1..13], &[0x0A; 12]);
+    }
+}